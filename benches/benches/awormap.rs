@@ -0,0 +1,82 @@
+use crdt_benches::{awormap, LARGE_MAP_SIZES, SET_SIZES};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rust_crdt_examples::state_crdt::{AWORMap, Convergent, MaxRegister};
+use std::time::Instant;
+
+fn merge_delta(c: &mut Criterion) {
+    let mut group = c.benchmark_group("awormap/merge_delta");
+    for size in SET_SIZES {
+        let map = awormap(size);
+
+        // the remote has seen the map, so its removal reaches an existing key
+        let mut remote: AWORMap<u64, MaxRegister<u64>> = AWORMap::new(1);
+        remote.merge(map.clone());
+        remote.take_delta();
+        remote.update(0, |v: &mut MaxRegister<u64>| v.set(size as u64));
+        remote.update(size as u64, |v: &mut MaxRegister<u64>| v.set(0));
+        remote.remove(&1);
+        let delta = remote.take_delta().unwrap();
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &map, |b, map| {
+            b.iter_batched(
+                || (map.clone(), delta.clone()),
+                |(mut map, delta)| {
+                    map.merge_delta(black_box(delta));
+                    map
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Merges a stream of one-key deltas into a single map, which is too
+/// large to clone for every iteration
+fn merge_small_delta(c: &mut Criterion) {
+    let mut group = c.benchmark_group("awormap/merge_small_delta");
+    group.sample_size(10);
+    for size in LARGE_MAP_SIZES {
+        let mut map = awormap(size);
+        let mut remote: AWORMap<u64, MaxRegister<u64>> = AWORMap::new(1);
+        remote.merge(map.clone());
+        remote.take_delta();
+
+        let size = size as u64;
+        let mut n = 0;
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter_custom(|iters| {
+                let deltas: Vec<_> = (0..iters)
+                    .map(|_| {
+                        n += 1;
+                        let key = n * 7919 % size;
+                        remote.update(key, |v| v.set(size + n));
+                        remote.remove(&((key + 1) % size));
+                        remote.take_delta().unwrap()
+                    })
+                    .collect();
+
+                let start = Instant::now();
+                for delta in deltas {
+                    map.merge_delta(black_box(delta));
+                }
+                start.elapsed()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("awormap/get");
+    for size in SET_SIZES {
+        let map = awormap(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &map, |b, map| {
+            b.iter(|| map.get(black_box(&(size as u64 / 2))))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, merge_delta, merge_small_delta, get);
+criterion_main!(benches);
@@ -0,0 +1,41 @@
+use crdt_benches::{aworset, SET_SIZES};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+fn add(c: &mut Criterion) {
+    let mut group = c.benchmark_group("aworset/add");
+    for size in SET_SIZES {
+        let set = aworset(size, 4);
+        group.bench_with_input(BenchmarkId::new("new", size), &set, |b, set| {
+            b.iter_batched_ref(
+                || set.clone(),
+                |set| set.add(black_box(size as u64)),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("existing", size), &set, |b, set| {
+            b.iter_batched_ref(
+                || set.clone(),
+                |set| set.add(black_box(size as u64 / 2)),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn contains(c: &mut Criterion) {
+    let mut group = c.benchmark_group("aworset/contains");
+    for size in SET_SIZES {
+        let set = aworset(size, 4);
+        group.bench_with_input(BenchmarkId::new("hit", size), &set, |b, set| {
+            b.iter(|| set.contains(black_box(&(size as u64 / 2))))
+        });
+        group.bench_with_input(BenchmarkId::new("miss", size), &set, |b, set| {
+            b.iter(|| set.contains(black_box(&(size as u64 * 2))))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, add, contains);
+criterion_main!(benches);
@@ -0,0 +1,79 @@
+use crdt_benches::{kernel, REPLICA_COUNTS, SET_SIZES};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rust_crdt_examples::state_crdt::causal::{Dot, DotContext};
+
+/// Context with `n` replicas and `events` dots each, where every other dot
+/// is missing so the ranges stay fragmented until `fill` is merged in
+fn gapped_context(n: usize, events: usize) -> (DotContext, DotContext) {
+    let mut context = DotContext::new();
+    let mut fill = DotContext::new();
+    for replica in 0..n as u64 {
+        for counter in 1..=events {
+            if counter % 2 == 0 {
+                context.add(Dot(replica, counter));
+            } else {
+                fill.add(Dot(replica, counter));
+            }
+        }
+    }
+    (context, fill)
+}
+
+fn context_add(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dot_context/add");
+    for n in REPLICA_COUNTS {
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter(|| {
+                let mut context = DotContext::new();
+                for replica in 0..n as u64 {
+                    for counter in (1..=100).rev() {
+                        context.add(Dot(replica, counter));
+                    }
+                }
+                context
+            })
+        });
+    }
+    group.finish();
+}
+
+fn context_merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dot_context/merge");
+    for n in REPLICA_COUNTS {
+        let (context, fill) = gapped_context(n, 100);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &context, |b, context| {
+            b.iter_batched(
+                || (context.clone(), fill.clone()),
+                |(mut context, fill)| {
+                    context.merge(fill);
+                    context
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn kernel_join(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dot_kernel/join");
+    for size in SET_SIZES {
+        let local = kernel(1, size, 0);
+        let other = kernel(2, size, size / 10);
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &local, |b, kernel| {
+            b.iter_batched(
+                || (kernel.clone(), other.clone()),
+                |(mut kernel, other)| {
+                    kernel.join(black_box(other));
+                    kernel
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, context_add, context_merge, kernel_join);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+use crdt_benches::{vclock, REPLICA_COUNTS};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+fn partial_cmp(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vclock/partial_cmp");
+    for n in REPLICA_COUNTS {
+        let a = vclock(n, 10);
+        let mut ahead = a.clone();
+        ahead.inc(n as u64 - 1);
+        let mut concurrent = a.clone();
+        concurrent.inc(0);
+
+        group.bench_with_input(BenchmarkId::new("equal", n), &a, |b, a| {
+            let other = a.clone();
+            b.iter(|| black_box(a).partial_cmp(black_box(&other)))
+        });
+        group.bench_with_input(BenchmarkId::new("less", n), &a, |b, a| {
+            b.iter(|| black_box(a).partial_cmp(black_box(&ahead)))
+        });
+        group.bench_with_input(BenchmarkId::new("concurrent", n), &ahead, |b, ahead| {
+            b.iter(|| black_box(ahead).partial_cmp(black_box(&concurrent)))
+        });
+    }
+    group.finish();
+}
+
+fn merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vclock/merge");
+    for n in REPLICA_COUNTS {
+        let a = vclock(n, 10);
+        let b_clock = vclock(n, 20);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &a, |b, a| {
+            b.iter_batched_ref(
+                || a.clone(),
+                |a| a.merge(black_box(&b_clock)),
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, partial_cmp, merge);
+criterion_main!(benches);
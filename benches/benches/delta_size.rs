@@ -0,0 +1,77 @@
+//! Prints the serialized size of deltas and full states, so changes to
+//! the wire format show up next to the timing benchmarks
+
+use crdt_benches::{awormap, aworset, REPLICA_COUNTS, SET_SIZES};
+use rust_crdt_examples::state_crdt::{Convergent, GCounter, MaxRegister, PNCounter};
+
+fn size<T: serde::Serialize>(value: &T) -> usize {
+    bincode::serialized_size(value).unwrap() as usize
+}
+
+fn main() {
+    println!(
+        "{:<24} {:>8} {:>8} {:>12} {:>12}",
+        "type", "size", "replicas", "delta", "state"
+    );
+
+    for replicas in REPLICA_COUNTS {
+        for elements in SET_SIZES {
+            let mut set = aworset(elements, replicas);
+            set.add(elements as u64);
+            let delta = set.take_delta().unwrap();
+            println!(
+                "{:<24} {:>8} {:>8} {:>12} {:>12}",
+                "AWORSet<u64>",
+                elements,
+                replicas,
+                size(&delta),
+                size(&set)
+            );
+        }
+    }
+
+    for elements in SET_SIZES {
+        let mut map = awormap(elements);
+        map.update(0, |v: &mut MaxRegister<u64>| v.set(elements as u64));
+        let delta = map.take_delta().unwrap();
+        println!(
+            "{:<24} {:>8} {:>8} {:>12} {:>12}",
+            "AWORMap<u64, MaxRegister>",
+            elements,
+            1,
+            size(&delta),
+            size(&map)
+        );
+    }
+
+    for replicas in REPLICA_COUNTS {
+        let mut counter = PNCounter::new();
+        for replica in 0..replicas as u64 {
+            counter.inc(replica);
+            counter.dec(replica);
+        }
+        let delta = counter.take_delta().unwrap();
+        println!(
+            "{:<24} {:>8} {:>8} {:>12} {:>12}",
+            "PNCounter",
+            "-",
+            replicas,
+            size(&delta),
+            size(&counter)
+        );
+
+        let mut counter: GCounter = GCounter::new();
+        for replica in 0..replicas as u64 {
+            counter.inc(replica).unwrap();
+        }
+        let delta = counter.take_delta().unwrap();
+        println!(
+            "{:<24} {:>8} {:>8} {:>12} {:>12}",
+            "GCounter",
+            "-",
+            replicas,
+            size(&delta),
+            size(&counter)
+        );
+    }
+}
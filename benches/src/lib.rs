@@ -0,0 +1,64 @@
+//! Shared fixtures for the benchmarks
+
+use rust_crdt_examples::state_crdt::{
+    AWORMap, AWORSet, Convergent, DotKernel, MaxRegister, ReplicaId,
+};
+use rust_crdt_examples::vclock::VClock;
+
+/// Replica counts the clock and context benchmarks are run for
+pub const REPLICA_COUNTS: [usize; 4] = [2, 8, 32, 128];
+
+/// Element counts the set and map benchmarks are run for
+pub const SET_SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+/// Map sizes small deltas are merged into, far enough apart
+/// to tell sub-linear cost from linear
+pub const LARGE_MAP_SIZES: [usize; 2] = [1_000, 1_000_000];
+
+/// Clock with `n` replicas that have seen `events` events each
+pub fn vclock(n: usize, events: usize) -> VClock {
+    let mut clock = VClock::new();
+    for replica in 0..n as ReplicaId {
+        for _ in 0..events {
+            clock.inc(replica);
+        }
+    }
+    clock
+}
+
+/// Set with `size` elements added round-robin by `replicas` replicas
+pub fn aworset(size: usize, replicas: usize) -> AWORSet<u64> {
+    let mut set = AWORSet::new(0);
+    for r in 0..replicas as ReplicaId {
+        let mut other = AWORSet::new(r);
+        for value in (r..size as u64).step_by(replicas) {
+            other.add(value);
+        }
+        set.merge(other);
+    }
+    set.take_delta();
+    set
+}
+
+/// Kernel of a set where `replica` added `size` elements and then
+/// removed the first `removed` of them
+pub fn kernel(replica: ReplicaId, size: usize, removed: usize) -> DotKernel<u64> {
+    let mut set = AWORSet::new(replica);
+    for value in 0..size as u64 {
+        set.add(value);
+    }
+    for value in 0..removed as u64 {
+        set.remove(&value);
+    }
+    set.take_delta().unwrap()
+}
+
+/// Map with `size` keys inserted by a single replica
+pub fn awormap(size: usize) -> AWORMap<u64, MaxRegister<u64>> {
+    let mut map: AWORMap<u64, MaxRegister<u64>> = AWORMap::new(0);
+    for key in 0..size as u64 {
+        map.update(key, |v| v.set(key));
+    }
+    map.take_delta();
+    map
+}
@@ -0,0 +1,468 @@
+//! Interactive playground for CRDT semantics: create named replicas, update
+//! them, ship states or deltas between them and look at their dots and
+//! clocks. Type `help` for the list of commands. Commands can be piped in
+//! as well, e.g. `cargo run --example repl < script.txt`.
+
+use rust_crdt_examples::state_crdt::causal::Dot;
+use rust_crdt_examples::state_crdt::{
+    AWORMap, AWORMapDelta, AWORSet, DotKernel, MaxRegister, PNCounter,
+};
+use rust_crdt_examples::{Convergent, ReplicaId};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+const HELP: &str = "\
+replicas:
+  new NAME counter|set|map [ID]   create a replica, ids are assigned in order if omitted
+  list                            print all replicas
+  show [NAME]                     print the state of a replica, or of all of them
+
+updates:
+  inc NAME [N]                    add N (default 1) to a counter
+  dec NAME [N]                    subtract N (default 1) from a counter
+  add NAME ELEMENT                add an element to a set
+  insert NAME KEY VALUE           assign a map key, concurrent assignments keep the greatest value
+  remove NAME ELEMENT|KEY         remove an element from a set or a key from a map
+
+replication:
+  merge TARGET SOURCE             merge the full state of SOURCE into TARGET
+  sync-delta TARGET SOURCE        take the delta accumulated by SOURCE and merge it into TARGET
+
+  help                            print this message
+  quit                            exit";
+
+type Map = AWORMap<String, MaxRegister<String>>;
+
+#[derive(Clone)]
+enum Value {
+    Counter(PNCounter),
+    Set(AWORSet<String>),
+    Map(Map),
+}
+
+enum Delta {
+    Counter(PNCounter),
+    Set(DotKernel<String>),
+    Map(AWORMapDelta<String, String>),
+}
+
+impl fmt::Display for Delta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Counter(c) => write!(f, "counter with clock {}", c.clock()),
+            Self::Set(d) => write!(f, "{}", kernel(d)),
+            Self::Map(d) => {
+                let mut parts = Vec::new();
+                if let Some(keys) = d.added() {
+                    parts.push(format!("keys {}", kernel(keys)));
+                }
+
+                let mut values: Vec<_> = d.values().collect();
+                values.sort();
+                if !values.is_empty() {
+                    let values: Vec<_> = values
+                        .iter()
+                        .map(|(k, v)| format!("{}: {}", k, v))
+                        .collect();
+                    parts.push(format!("values {{{}}}", values.join(", ")));
+                }
+
+                let mut removed: Vec<_> = d.removed().collect();
+                removed.sort_by_key(|(k, _)| *k);
+                if !removed.is_empty() {
+                    let removed: Vec<_> = removed
+                        .iter()
+                        .map(|(k, dots)| format!("{} {}", k, dots))
+                        .collect();
+                    parts.push(format!("removed {{{}}}", removed.join(", ")));
+                }
+                write!(f, "{}", parts.join(" "))
+            }
+        }
+    }
+}
+
+/// Renders the elements of a kernel with their dots, followed by its context
+fn kernel(d: &DotKernel<String>) -> String {
+    let mut entries: Vec<_> = d.store.0.iter().collect();
+    entries.sort();
+    let entries: Vec<_> = entries
+        .iter()
+        .map(|(dot, k)| format!("{} {}", k, dot))
+        .collect();
+    format!("{{{}}} context {}", entries.join(", "), d.context)
+}
+
+#[derive(Clone)]
+struct Replica {
+    id: ReplicaId,
+    value: Value,
+}
+
+impl Replica {
+    fn kind(&self) -> &'static str {
+        match self.value {
+            Value::Counter(_) => "counter",
+            Value::Set(_) => "set",
+            Value::Map(_) => "map",
+        }
+    }
+
+    /// Renders the value along with the dots and clocks behind it
+    fn describe(&self, name: &str) -> String {
+        let mut out = format!("{} ({}, replica {})", name, self.kind(), self.id);
+        match &self.value {
+            Value::Counter(c) => {
+                out += &format!(" = {}\n  clock: {}", c.value(), c.clock());
+            }
+            Value::Set(s) => {
+                out += &format!(" = {{{}}}", sorted(s.keys()).join(", "));
+                for k in sorted(s.keys()) {
+                    out += &format!("\n  {}: {}", k, dots(s.dots(&k)));
+                }
+                out += &format!("\n  context: {}\n  clock: {}", s.context(), s.clock());
+            }
+            Value::Map(m) => {
+                out += &format!(" = {{{}}}", map_entries(m).join(", "));
+                for k in sorted(m.keys()) {
+                    out += &format!("\n  {}: {}", k, dots(m.dots(&k)));
+                }
+                out += &format!("\n  context: {}\n  clock: {}", m.context(), m.clock());
+            }
+        }
+        out
+    }
+}
+
+fn sorted<'a>(keys: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let mut keys: Vec<_> = keys.cloned().collect();
+    keys.sort();
+    keys
+}
+
+fn map_entries(m: &Map) -> Vec<String> {
+    sorted(m.keys())
+        .into_iter()
+        .map(|k| {
+            let v = m.get(&k).and_then(|r| r.value()).map_or("", |v| v);
+            format!("{}: {}", k, v)
+        })
+        .collect()
+}
+
+fn dots(dots: impl Iterator<Item = Dot>) -> String {
+    let mut dots: Vec<_> = dots.collect();
+    dots.sort();
+    let dots: Vec<_> = dots.iter().map(|d| d.to_string()).collect();
+    format!("dots {}", dots.join(" "))
+}
+
+#[derive(Default)]
+struct Repl {
+    replicas: BTreeMap<String, Replica>,
+    next_id: ReplicaId,
+}
+
+impl Repl {
+    fn run(&mut self, words: &[&str]) -> Result<String, String> {
+        match words {
+            ["new", name, kind, rest @ ..] => self.create(name, kind, rest),
+            ["list"] => Ok(self
+                .replicas
+                .iter()
+                .map(|(name, r)| format!("{} ({}, replica {})", name, r.kind(), r.id))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            ["show"] => Ok(self
+                .replicas
+                .iter()
+                .map(|(name, r)| r.describe(name))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            ["show", name] => Ok(self.get(name)?.describe(name)),
+            ["inc", name, rest @ ..] => {
+                let n = count(rest)?;
+                let r = self.get_mut(name)?;
+                match &mut r.value {
+                    Value::Counter(c) => c.add(r.id, n).map_err(|e| e.to_string())?,
+                    _ => return Err(format!("{} is not a counter", name)),
+                }
+                self.show(name)
+            }
+            ["dec", name, rest @ ..] => {
+                let n = count(rest)?;
+                let r = self.get_mut(name)?;
+                match &mut r.value {
+                    Value::Counter(c) => c.sub(r.id, n).map_err(|e| e.to_string())?,
+                    _ => return Err(format!("{} is not a counter", name)),
+                }
+                self.show(name)
+            }
+            ["add", name, element] => {
+                match &mut self.get_mut(name)?.value {
+                    Value::Set(s) => s.add(element.to_string()),
+                    _ => return Err(format!("{} is not a set", name)),
+                }
+                self.show(name)
+            }
+            ["insert", name, key, value] => {
+                match &mut self.get_mut(name)?.value {
+                    Value::Map(m) => m.update(key.to_string(), |r: &mut MaxRegister<String>| {
+                        r.set(value.to_string())
+                    }),
+                    _ => return Err(format!("{} is not a map", name)),
+                }
+                self.show(name)
+            }
+            ["remove", name, element] => {
+                match &mut self.get_mut(name)?.value {
+                    Value::Set(s) => s.remove(*element),
+                    Value::Map(m) => m.remove(*element),
+                    Value::Counter(_) => return Err(format!("{} is a counter", name)),
+                }
+                self.show(name)
+            }
+            ["merge", target, source] => {
+                let source = self.get(source)?.value.clone();
+                match (&mut self.get_mut(target)?.value, source) {
+                    (Value::Counter(a), Value::Counter(b)) => a.merge(b),
+                    (Value::Set(a), Value::Set(b)) => a.merge(b),
+                    (Value::Map(a), Value::Map(b)) => a.merge(b),
+                    _ => return Err("replicas are of different types".to_owned()),
+                }
+                self.show(target)
+            }
+            ["sync-delta", target, source] => self.sync_delta(target, source),
+            ["help"] => Ok(HELP.to_owned()),
+            _ => Err("unknown command, try `help`".to_owned()),
+        }
+    }
+
+    fn create(&mut self, name: &str, kind: &str, rest: &[&str]) -> Result<String, String> {
+        if self.replicas.contains_key(name) {
+            return Err(format!("{} already exists", name));
+        }
+        let id = match rest {
+            [] => self
+                .next_id
+                .checked_add(1)
+                .ok_or_else(|| "no replica ids left".to_owned())?,
+            [id] => id
+                .parse()
+                .map_err(|_| format!("invalid replica id: {}", id))?,
+            _ => return Err("too many arguments".to_owned()),
+        };
+        if let Some((other, _)) = self.replicas.iter().find(|(_, r)| r.id == id) {
+            return Err(format!("replica id {} is taken by {}", id, other));
+        }
+        let value = match kind {
+            "counter" => Value::Counter(PNCounter::new()),
+            "set" => Value::Set(AWORSet::new(id)),
+            "map" => Value::Map(Map::new(id)),
+            _ => return Err(format!("unknown type: {}", kind)),
+        };
+
+        self.next_id = self.next_id.max(id);
+        self.replicas.insert(name.to_owned(), Replica { id, value });
+        self.show(name)
+    }
+
+    /// Takes the delta of the source and merges it into the target, printing
+    /// the delta first. The source starts a new delta afterwards, so deltas
+    /// meant for several replicas have to be synced before taking them.
+    fn sync_delta(&mut self, target: &str, source: &str) -> Result<String, String> {
+        // checked before the delta is taken, which would lose it
+        if self.get(target)?.kind() != self.get(source)?.kind() {
+            return Err("replicas are of different types".to_owned());
+        }
+        let delta = match &mut self.get_mut(source)?.value {
+            Value::Counter(c) => c.take_delta().map(Delta::Counter),
+            Value::Set(s) => s.take_delta().map(Delta::Set),
+            Value::Map(m) => m.take_delta().map(Delta::Map),
+        };
+        let Some(delta) = delta else {
+            return Ok(format!("{} has no delta", source));
+        };
+
+        let shipped = delta.to_string();
+        match (&mut self.get_mut(target)?.value, delta) {
+            (Value::Counter(a), Delta::Counter(d)) => a.merge_delta(d),
+            (Value::Set(a), Delta::Set(d)) => a.merge_delta(d),
+            (Value::Map(a), Delta::Map(d)) => a.merge_delta(d),
+            _ => return Err("replicas are of different types".to_owned()),
+        }
+        Ok(format!("delta: {}\n{}", shipped, self.show(target)?))
+    }
+
+    fn show(&self, name: &str) -> Result<String, String> {
+        Ok(self.get(name)?.describe(name))
+    }
+
+    fn get(&self, name: &str) -> Result<&Replica, String> {
+        self.replicas
+            .get(name)
+            .ok_or_else(|| format!("no such replica: {}", name))
+    }
+
+    fn get_mut(&mut self, name: &str) -> Result<&mut Replica, String> {
+        self.replicas
+            .get_mut(name)
+            .ok_or_else(|| format!("no such replica: {}", name))
+    }
+}
+
+fn count(args: &[&str]) -> Result<usize, String> {
+    match args {
+        [] => Ok(1),
+        [n] => n.parse().map_err(|_| format!("invalid number: {}", n)),
+        _ => Err("too many arguments".to_owned()),
+    }
+}
+
+fn main() {
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        println!("CRDT playground, type `help` for the list of commands");
+    }
+
+    let mut repl = Repl::default();
+    let mut out = io::stdout();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        if interactive {
+            print!("> ");
+            let _ = out.flush();
+        }
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => continue,
+            ["quit" | "exit"] => break,
+            words => match repl.run(words) {
+                Ok(reply) if reply.is_empty() => {}
+                Ok(reply) => println!("{}", reply),
+                Err(e) => println!("error: {}", e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(repl: &mut Repl, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        repl.run(&words)
+    }
+
+    #[test]
+    fn counters() {
+        let mut repl = Repl::default();
+        run(&mut repl, "new a counter").unwrap();
+        run(&mut repl, "new b counter").unwrap();
+        run(&mut repl, "inc a 3").unwrap();
+        run(&mut repl, "dec b").unwrap();
+
+        let reply = run(&mut repl, "merge a b").unwrap();
+        assert_eq!(reply, "a (counter, replica 1) = 2\n  clock: {1: 3, 2: 1}");
+    }
+
+    #[test]
+    fn set_delta() {
+        let mut repl = Repl::default();
+        run(&mut repl, "new a set").unwrap();
+        run(&mut repl, "new b set").unwrap();
+        run(&mut repl, "add a foo").unwrap();
+        run(&mut repl, "add a bar").unwrap();
+
+        let reply = run(&mut repl, "sync-delta b a").unwrap();
+        assert!(reply.starts_with("delta: {foo (1, 1), bar (1, 2)} context {1: [1..=2]}\n"));
+        assert!(reply.contains("b (set, replica 2) = {bar, foo}"));
+        assert_eq!(run(&mut repl, "sync-delta b a").unwrap(), "a has no delta");
+    }
+
+    #[test]
+    fn map_delta() {
+        let mut repl = Repl::default();
+        run(&mut repl, "new a map").unwrap();
+        run(&mut repl, "new b map").unwrap();
+        run(&mut repl, "insert a x 1").unwrap();
+        run(&mut repl, "sync-delta b a").unwrap();
+
+        run(&mut repl, "remove a x").unwrap();
+        let reply = run(&mut repl, "sync-delta b a").unwrap();
+        assert!(reply.starts_with("delta: keys {} context {1: [1..=2]} removed {x {1: [1]}}\n"));
+        assert!(reply.contains("b (map, replica 2) = {}"));
+    }
+
+    #[test]
+    fn replica_names_and_ids_are_unique() {
+        let mut repl = Repl::default();
+        run(&mut repl, "new a set 5").unwrap();
+        assert_eq!(
+            run(&mut repl, "new a set"),
+            Err("a already exists".to_owned())
+        );
+        assert_eq!(
+            run(&mut repl, "new b map 5"),
+            Err("replica id 5 is taken by a".to_owned())
+        );
+
+        // ids are assigned past the highest one taken
+        run(&mut repl, "new b map").unwrap();
+        assert_eq!(
+            run(&mut repl, "list").unwrap(),
+            "a (set, replica 5)\nb (map, replica 6)"
+        );
+
+        run(&mut repl, "new c set 18446744073709551615").unwrap();
+        assert_eq!(
+            run(&mut repl, "new d set"),
+            Err("no replica ids left".to_owned())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut repl = Repl::default();
+        run(&mut repl, "new a set").unwrap();
+        run(&mut repl, "new b counter").unwrap();
+
+        assert_eq!(
+            run(&mut repl, "inc a"),
+            Err("a is not a counter".to_owned())
+        );
+        assert_eq!(
+            run(&mut repl, "remove b x"),
+            Err("b is a counter".to_owned())
+        );
+        assert_eq!(
+            run(&mut repl, "merge a b"),
+            Err("replicas are of different types".to_owned())
+        );
+        run(&mut repl, "add a x").unwrap();
+        assert_eq!(
+            run(&mut repl, "sync-delta b a"),
+            Err("replicas are of different types".to_owned())
+        );
+        // the delta is still there for a replica of the same type
+        run(&mut repl, "new c set").unwrap();
+        run(&mut repl, "sync-delta c a").unwrap();
+        assert!(run(&mut repl, "show c").unwrap().contains('x'));
+
+        assert_eq!(
+            run(&mut repl, "show d"),
+            Err("no such replica: d".to_owned())
+        );
+        assert_eq!(
+            run(&mut repl, "inc b x"),
+            Err("invalid number: x".to_owned())
+        );
+        assert!(run(&mut repl, "frobnicate").is_err());
+    }
+}
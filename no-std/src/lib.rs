@@ -0,0 +1,81 @@
+//! Exercises the core types from a `no_std` crate, the library is built
+//! without its `std` feature so any `std` path in it fails the build
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use rust_crdt_examples::state_crdt::{AWORSet, DotKernel, GCounter, PNCounter};
+use rust_crdt_examples::vclock::VClock;
+use rust_crdt_examples::{Convergent, ReplicaId};
+
+/// Syncs two set replicas through deltas and returns the merged elements
+pub fn sync_sets(a: ReplicaId, b: ReplicaId) -> Vec<u32> {
+    let mut s1 = AWORSet::new(a);
+    let mut s2 = AWORSet::new(b);
+
+    s1.add(1);
+    s1.add(2);
+    s2.add(3);
+
+    let delta: DotKernel<u32> = s1.take_delta().unwrap();
+    s2.merge_delta(delta);
+    s2.remove(&1);
+    s1.merge_delta(s2.take_delta().unwrap());
+
+    let mut keys: Vec<u32> = s1.keys().copied().collect();
+    keys.sort_unstable();
+    keys
+}
+
+/// Merges two counter replicas and returns the value
+pub fn sync_counters(a: ReplicaId, b: ReplicaId) -> i128 {
+    let mut c1 = PNCounter::new();
+    let mut c2 = PNCounter::new();
+
+    c1.inc(a);
+    c1.inc(a);
+    c2.dec(b);
+    c1.merge(c2);
+
+    let mut g: GCounter = GCounter::new();
+    g.saturating_add(a, 1);
+    c1.value() + g.value() as i128
+}
+
+/// Returns whether the merged clock descends from both inputs
+pub fn merge_clocks(a: ReplicaId, b: ReplicaId) -> bool {
+    let mut c1 = VClock::new();
+    let mut c2 = VClock::new();
+
+    c1.inc(a);
+    c2.inc(b);
+
+    let mut merged = c1.clone();
+    merged.merge(&c2);
+    c1.concurrent(&c2) && merged.descends(&c1) && merged.descends(&c2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    #[test]
+    fn sets() {
+        assert_eq!(sync_sets(REPLICA_1, REPLICA_2), [2, 3]);
+    }
+
+    #[test]
+    fn counters() {
+        assert_eq!(sync_counters(REPLICA_1, REPLICA_2), 2);
+    }
+
+    #[test]
+    fn clocks() {
+        assert!(merge_clocks(REPLICA_1, REPLICA_2));
+    }
+}
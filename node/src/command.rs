@@ -0,0 +1,247 @@
+//! Line based commands read by the node from its standard input
+
+use crate::replicas::{Error, ObjectDelta, Replicas};
+use std::fmt;
+use std::net::SocketAddr;
+
+pub const HELP: &str = "\
+commands:
+  inc NAME [N]            add N (default 1) to a counter
+  dec NAME [N]            subtract N (default 1) from a counter
+  add NAME ELEMENT        add an element to a set
+  remove NAME ELEMENT     remove an element from a set
+  incr NAME KEY [N]       add N (default 1) to the counter under a map key
+  decr NAME KEY [N]       subtract N (default 1) from the counter under a map key
+  delete NAME KEY         remove a map key
+  get NAME                print the value of an object
+  list                    print all objects
+  connect ADDR            sync with another node
+  help                    print this message
+  quit                    exit";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Command {
+    Inc { name: String, n: usize },
+    Dec { name: String, n: usize },
+    Add { name: String, element: String },
+    Remove { name: String, element: String },
+    Incr { name: String, key: String, n: usize },
+    Decr { name: String, key: String, n: usize },
+    Delete { name: String, key: String },
+    Get { name: String },
+    List,
+    Connect { addr: SocketAddr },
+    Help,
+    Quit,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Command {
+    /// Parses a line, `None` if it is blank
+    pub fn parse(line: &str) -> Option<Result<Self, ParseError>> {
+        let mut words = line.split_whitespace();
+        let command = words.next()?;
+        let args: Vec<&str> = words.collect();
+        Some(Self::parse_args(command, &args))
+    }
+
+    fn parse_args(command: &str, args: &[&str]) -> Result<Self, ParseError> {
+        let cmd = match (command, args) {
+            ("inc", [name, rest @ ..]) => Self::Inc {
+                name: name.to_string(),
+                n: count(rest)?,
+            },
+            ("dec", [name, rest @ ..]) => Self::Dec {
+                name: name.to_string(),
+                n: count(rest)?,
+            },
+            ("add", [name, element]) => Self::Add {
+                name: name.to_string(),
+                element: element.to_string(),
+            },
+            ("remove", [name, element]) => Self::Remove {
+                name: name.to_string(),
+                element: element.to_string(),
+            },
+            ("incr", [name, key, rest @ ..]) => Self::Incr {
+                name: name.to_string(),
+                key: key.to_string(),
+                n: count(rest)?,
+            },
+            ("decr", [name, key, rest @ ..]) => Self::Decr {
+                name: name.to_string(),
+                key: key.to_string(),
+                n: count(rest)?,
+            },
+            ("delete", [name, key]) => Self::Delete {
+                name: name.to_string(),
+                key: key.to_string(),
+            },
+            ("get", [name]) => Self::Get {
+                name: name.to_string(),
+            },
+            ("list", []) => Self::List,
+            ("connect", [addr]) => Self::Connect {
+                addr: addr
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid address: {}", addr)))?,
+            },
+            ("help", []) => Self::Help,
+            ("quit" | "exit", []) => Self::Quit,
+            (
+                "inc" | "dec" | "add" | "remove" | "incr" | "decr" | "delete" | "get" | "list"
+                | "connect" | "help" | "quit" | "exit",
+                _,
+            ) => return Err(ParseError(format!("wrong arguments for {}", command))),
+            _ => return Err(ParseError(format!("unknown command: {}", command))),
+        };
+        Ok(cmd)
+    }
+
+    /// Applies an update to the replicas, returning the name of the updated
+    /// object and the delta to send to peers. Queries and node level
+    /// commands leave the replicas untouched and return `None`.
+    pub fn apply(&self, replicas: &mut Replicas) -> Result<Option<(String, ObjectDelta)>, Error> {
+        let (name, delta) = match self {
+            Self::Inc { name, n } => {
+                let delta =
+                    replicas.update_counter(name, |c, id| c.add(id, *n).map_err(overflow))?;
+                (name, Some(delta))
+            }
+            Self::Dec { name, n } => {
+                let delta =
+                    replicas.update_counter(name, |c, id| c.sub(id, *n).map_err(overflow))?;
+                (name, Some(delta))
+            }
+            Self::Add { name, element } => {
+                (name, replicas.update_set(name, |s| s.add(element.clone()))?)
+            }
+            Self::Remove { name, element } => {
+                (name, replicas.update_set(name, |s| s.remove(element))?)
+            }
+            Self::Incr { name, key, n } => {
+                let delta = replicas.update_map(name, |m, id| {
+                    let mut result = Ok(());
+                    m.update(key.clone(), |c| result = c.add(id, *n).map_err(overflow));
+                    result
+                })?;
+                (name, delta)
+            }
+            Self::Decr { name, key, n } => {
+                let delta = replicas.update_map(name, |m, id| {
+                    let mut result = Ok(());
+                    m.update(key.clone(), |c| result = c.sub(id, *n).map_err(overflow));
+                    result
+                })?;
+                (name, delta)
+            }
+            Self::Delete { name, key } => (
+                name,
+                replicas.update_map(name, |m, _| {
+                    m.remove(key);
+                    Ok(())
+                })?,
+            ),
+            _ => return Ok(None),
+        };
+
+        Ok(delta.map(|d| (name.clone(), d)))
+    }
+}
+
+fn count(args: &[&str]) -> Result<usize, ParseError> {
+    match args {
+        [] => Ok(1),
+        [n] => n
+            .parse()
+            .map_err(|_| ParseError(format!("invalid number: {}", n))),
+        _ => Err(ParseError("too many arguments".to_owned())),
+    }
+}
+
+fn overflow<E>(_: E) -> Error {
+    Error::Overflow
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Command, ParseError> {
+        Command::parse(line).unwrap()
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert!(Command::parse("   ").is_none());
+        assert_eq!(
+            parse("inc hits"),
+            Ok(Command::Inc {
+                name: "hits".to_owned(),
+                n: 1
+            })
+        );
+        assert_eq!(
+            parse(" decr votes  no 3 "),
+            Ok(Command::Decr {
+                name: "votes".to_owned(),
+                key: "no".to_owned(),
+                n: 3
+            })
+        );
+        assert_eq!(
+            parse("connect 127.0.0.1:7001"),
+            Ok(Command::Connect {
+                addr: "127.0.0.1:7001".parse().unwrap()
+            })
+        );
+
+        assert_eq!(
+            parse("inc hits x"),
+            Err(ParseError("invalid number: x".to_owned()))
+        );
+        assert_eq!(
+            parse("add fruits"),
+            Err(ParseError("wrong arguments for add".to_owned()))
+        );
+        assert_eq!(
+            parse("frobnicate"),
+            Err(ParseError("unknown command: frobnicate".to_owned()))
+        );
+    }
+
+    #[test]
+    fn apply_updates() {
+        let mut replicas = Replicas::new(1);
+        for line in [
+            "inc hits 5",
+            "dec hits",
+            "add fruits apple",
+            "incr votes yes 2",
+        ] {
+            let (name, _) = parse(line).unwrap().apply(&mut replicas).unwrap().unwrap();
+            assert_eq!(name, line.split(' ').nth(1).unwrap());
+        }
+        assert!(parse("list")
+            .unwrap()
+            .apply(&mut replicas)
+            .unwrap()
+            .is_none());
+
+        assert_eq!(replicas.get("hits").unwrap().to_string(), "4");
+        assert_eq!(replicas.get("votes").unwrap().to_string(), "{yes: 2}");
+
+        let err = parse("add hits foo").unwrap().apply(&mut replicas);
+        assert_eq!(err.unwrap_err().to_string(), "hits is a counter");
+    }
+}
@@ -0,0 +1,73 @@
+//! Framing of the messages exchanged between nodes: a little-endian `u32`
+//! length followed by the bincode payload. TCP already guarantees integrity
+//! and ordering, so unlike the storage records there is no checksum.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Upper bound on a single frame, so a corrupt length can't make
+/// the reader allocate arbitrary amounts of memory
+pub const MAX_FRAME_LEN: usize = 64 << 20;
+
+pub fn write_frame<W: Write, T: Serialize>(w: &mut W, msg: &T) -> io::Result<()> {
+    let payload = bincode::serialize(msg).map_err(io::Error::other)?;
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "frame too large"));
+    }
+
+    w.write_all(&(payload.len() as u32).to_le_bytes())?;
+    w.write_all(&payload)?;
+    w.flush()
+}
+
+/// Reads the next frame, `None` if the stream was closed between frames
+pub fn read_frame<R: Read, T: DeserializeOwned>(r: &mut R) -> io::Result<Option<T>> {
+    let mut len = [0; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(ErrorKind::InvalidData, "frame too large"));
+    }
+
+    let mut payload = vec![0; len];
+    r.read_exact(&mut payload)?;
+    bincode::deserialize(&payload)
+        .map(Some)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &"foo".to_owned()).unwrap();
+        write_frame(&mut buf, &42u64).unwrap();
+
+        let mut r = buf.as_slice();
+        assert_eq!(read_frame::<_, String>(&mut r).unwrap().unwrap(), "foo");
+        assert_eq!(read_frame::<_, u64>(&mut r).unwrap(), Some(42));
+        assert_eq!(read_frame::<_, u64>(&mut r).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_frames() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &42u64).unwrap();
+        buf.pop();
+        let err = read_frame::<_, u64>(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        let buf = u32::MAX.to_le_bytes();
+        let err = read_frame::<_, u64>(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
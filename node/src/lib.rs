@@ -0,0 +1,10 @@
+//! Node hosting named CRDTs and replicating them with its peers over TCP,
+//! the library behind the `crdt-node` binary.
+
+pub mod command;
+pub mod frame;
+pub mod node;
+pub mod replicas;
+
+pub use command::Command;
+pub use node::{Node, NodeOptions};
@@ -0,0 +1,126 @@
+use crdt_node::command::{Command, HELP};
+use crdt_node::{Node, NodeOptions};
+use rust_crdt_examples::ReplicaId;
+use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage: crdt-node --id REPLICA_ID [--listen ADDR] [--peer ADDR]... [--sync-interval MS]
+
+Hosts named counters, sets and maps, syncing them with the peers over TCP.
+Reads commands from standard input, one per line, try `help`.
+
+options:
+  --id REPLICA_ID         unique id of this replica
+  --listen ADDR           address to accept peers on [default: 127.0.0.1:0]
+  --peer ADDR             peer to connect to, can be repeated
+  --sync-interval MS      how often to exchange version vectors [default: 1000]";
+
+struct Args {
+    replica_id: ReplicaId,
+    listen: SocketAddr,
+    peers: Vec<SocketAddr>,
+    options: NodeOptions,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut replica_id = None;
+    let mut listen = SocketAddr::from(([127, 0, 0, 1], 0));
+    let mut peers = Vec::new();
+    let mut options = NodeOptions::default();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--id" => replica_id = Some(parse(&value()?)?),
+            "--listen" => listen = parse(&value()?)?,
+            "--peer" => peers.push(parse(&value()?)?),
+            "--sync-interval" => {
+                options.sync_interval = Duration::from_millis(parse(&value()?)?);
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok(Args {
+        replica_id: replica_id.ok_or("missing --id")?,
+        listen,
+        peers,
+        options,
+    })
+}
+
+fn parse<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid value: {}", s))
+}
+
+fn main() {
+    let args = parse_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(2);
+    });
+
+    let node = Node::bind(args.replica_id, args.listen, args.options).unwrap_or_else(|e| {
+        eprintln!("cannot listen on {}: {}", args.listen, e);
+        process::exit(1);
+    });
+    for peer in args.peers {
+        node.connect(peer);
+    }
+
+    let errors = node.errors();
+    thread::spawn(move || {
+        for e in errors {
+            eprintln!("dropping delta: {}", e);
+        }
+    });
+
+    let mut out = io::stdout().lock();
+    // printed first so scripts can pick up the port when listening on port 0
+    let _ = writeln!(out, "listening on {}", node.local_addr());
+
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let command = match Command::parse(&line) {
+            Some(Ok(command)) => command,
+            Some(Err(e)) => {
+                let _ = writeln!(out, "error: {}", e);
+                continue;
+            }
+            None => continue,
+        };
+
+        let reply = match &command {
+            Command::Get { name } => node.replicas().get(name).map(|o| o.to_string()),
+            Command::List => Ok(node
+                .replicas()
+                .iter()
+                .map(|(name, o)| format!("{} {} = {}", o.kind(), name, o))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Connect { addr } => {
+                node.connect(*addr);
+                Ok("ok".to_owned())
+            }
+            Command::Help => Ok(HELP.to_owned()),
+            Command::Quit => break,
+            _ => node.apply(&command).map(|()| "ok".to_owned()),
+        };
+
+        let _ = match reply {
+            Ok(reply) if reply.is_empty() => Ok(()),
+            Ok(reply) => writeln!(out, "{}", reply),
+            Err(e) => writeln!(out, "error: {}", e),
+        };
+        let _ = out.flush();
+    }
+}
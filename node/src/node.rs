@@ -0,0 +1,292 @@
+//! Replication between nodes over TCP.
+//!
+//! Every connection is symmetric. Deltas of local updates are sent to all
+//! connected peers right away. In addition, each side periodically sends
+//! the version vectors of its objects and the other side replies with the
+//! deltas since, so peers catch up after (re)connecting and updates reach
+//! nodes which are not directly connected to their origin.
+
+use crate::command::Command;
+use crate::frame::{read_frame, write_frame};
+use crate::replicas::{Error, ObjectDelta, Replicas};
+use rust_crdt_examples::vclock::VClock;
+use rust_crdt_examples::ReplicaId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, BufWriter};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+const RECONNECT_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    /// Version vectors of the sender's objects, asking for what it misses
+    Sync(BTreeMap<String, VClock>),
+    Delta(String, ObjectDelta),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NodeOptions {
+    /// How often connected peers exchange version vectors
+    pub sync_interval: Duration,
+}
+
+impl Default for NodeOptions {
+    fn default() -> Self {
+        Self {
+            sync_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+struct Peer {
+    id: usize,
+    tx: Sender<Message>,
+}
+
+struct Shared {
+    replicas: Mutex<Replicas>,
+    peers: Mutex<Vec<Peer>>,
+    next_peer: AtomicUsize,
+    // receives the errors of deltas from peers which couldn't be merged
+    errors: Mutex<Option<Sender<Error>>>,
+    options: NodeOptions,
+}
+
+/// Handle to a running node, cheap to clone
+#[derive(Clone)]
+pub struct Node {
+    shared: Arc<Shared>,
+    addr: SocketAddr,
+}
+
+impl Node {
+    /// Starts listening for peers on the address, port 0 picks a free one
+    pub fn bind(replica_id: ReplicaId, addr: SocketAddr, options: NodeOptions) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let node = Self {
+            shared: Arc::new(Shared {
+                replicas: Mutex::new(Replicas::new(replica_id)),
+                peers: Mutex::new(Vec::new()),
+                next_peer: AtomicUsize::new(0),
+                errors: Mutex::new(None),
+                options,
+            }),
+            addr: listener.local_addr()?,
+        };
+
+        let shared = node.shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let shared = shared.clone();
+                thread::spawn(move || run_connection(&shared, stream));
+            }
+        });
+
+        Ok(node)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn replicas(&self) -> MutexGuard<'_, Replicas> {
+        self.shared.replicas.lock().unwrap()
+    }
+
+    /// Returns a channel receiving the errors of deltas from peers which
+    /// couldn't be merged, replacing the previous one. Without a channel
+    /// such deltas are dropped silently.
+    pub fn errors(&self) -> Receiver<Error> {
+        let (tx, rx) = mpsc::channel();
+        *self.shared.errors.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    /// Number of currently open connections
+    pub fn peer_count(&self) -> usize {
+        self.shared.peers.lock().unwrap().len()
+    }
+
+    /// Keeps a connection to the peer open in the background,
+    /// reconnecting whenever it is lost
+    pub fn connect(&self, addr: SocketAddr) {
+        let shared = self.shared.clone();
+        thread::spawn(move || loop {
+            if let Ok(stream) = TcpStream::connect(addr) {
+                run_connection(&shared, stream);
+            }
+            thread::sleep(RECONNECT_DELAY);
+        });
+    }
+
+    /// Applies a local update and sends its delta to the connected peers
+    pub fn apply(&self, command: &Command) -> Result<(), Error> {
+        let mut replicas = self.replicas();
+        if let Some((name, delta)) = command.apply(&mut replicas)? {
+            // still under the lock, so peers get deltas in the order applied
+            self.broadcast(Message::Delta(name, delta));
+        }
+        Ok(())
+    }
+
+    fn broadcast(&self, msg: Message) {
+        for peer in self.shared.peers.lock().unwrap().iter() {
+            // a closed channel means the connection is going away
+            let _ = peer.tx.send(msg.clone());
+        }
+    }
+}
+
+/// Serves the connection until either side closes it
+fn run_connection(shared: &Arc<Shared>, stream: TcpStream) {
+    let (Ok(read_half), Ok(write_half)) = (stream.try_clone(), stream.try_clone()) else {
+        return;
+    };
+    let _ = stream.set_nodelay(true);
+
+    let id = shared.next_peer.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = mpsc::channel();
+    shared
+        .peers
+        .lock()
+        .unwrap()
+        .push(Peer { id, tx: tx.clone() });
+
+    let writer = {
+        let shared = shared.clone();
+        thread::spawn(move || {
+            let mut w = BufWriter::new(write_half);
+            let interval = shared.options.sync_interval;
+            let mut msg = Message::Sync(shared.replicas.lock().unwrap().clocks());
+            loop {
+                if write_frame(&mut w, &msg).is_err() {
+                    break;
+                }
+                msg = match rx.recv_timeout(interval) {
+                    Ok(msg) => msg,
+                    Err(RecvTimeoutError::Timeout) => {
+                        Message::Sync(shared.replicas.lock().unwrap().clocks())
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+            }
+            // unblocks the reader if the write failed
+            let _ = w.get_ref().shutdown(Shutdown::Both);
+        })
+    };
+
+    let mut r = BufReader::new(read_half);
+    while let Ok(Some(msg)) = read_frame::<_, Message>(&mut r) {
+        match msg {
+            Message::Sync(clocks) => {
+                let deltas = shared.replicas.lock().unwrap().deltas_since(&clocks);
+                for (name, delta) in deltas {
+                    let _ = tx.send(Message::Delta(name, delta));
+                }
+            }
+            Message::Delta(name, delta) => {
+                let result = shared.replicas.lock().unwrap().merge_delta(&name, delta);
+                if let (Err(e), Some(errors)) = (result, &*shared.errors.lock().unwrap()) {
+                    let _ = errors.send(e);
+                }
+            }
+        }
+    }
+
+    // dropping the last sender stops the writer
+    shared.peers.lock().unwrap().retain(|p| p.id != id);
+    drop(tx);
+    let _ = stream.shutdown(Shutdown::Both);
+    let _ = writer.join();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicas::Kind;
+    use std::time::Instant;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+    const REPLICA_3: ReplicaId = 789;
+
+    fn start(replica_id: ReplicaId) -> Node {
+        let options = NodeOptions {
+            sync_interval: Duration::from_millis(50),
+        };
+        Node::bind(replica_id, "127.0.0.1:0".parse().unwrap(), options).unwrap()
+    }
+
+    fn run(node: &Node, line: &str) {
+        node.apply(&Command::parse(line).unwrap().unwrap()).unwrap();
+    }
+
+    fn wait_for(nodes: &[&Node], name: &str, expected: &str) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        for node in nodes {
+            loop {
+                let value = node.replicas().get(name).map(|o| o.to_string());
+                if value.as_deref() == Ok(expected) {
+                    break;
+                }
+                assert!(Instant::now() < deadline, "{} is {:?}", name, value);
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    #[test]
+    fn converge_over_a_chain() {
+        let n1 = start(REPLICA_1);
+        let n2 = start(REPLICA_2);
+        let n3 = start(REPLICA_3);
+
+        // updates made before connecting are caught up on the first sync
+        run(&n1, "inc hits 2");
+        run(&n3, "add fruits apple");
+
+        // n1 and n3 only talk through n2
+        n1.connect(n2.local_addr());
+        n3.connect(n2.local_addr());
+
+        run(&n2, "dec hits");
+        run(&n1, "add fruits pear");
+        run(&n3, "incr votes yes 3");
+        run(&n2, "decr votes yes");
+
+        wait_for(&[&n1, &n2, &n3], "hits", "1");
+        wait_for(&[&n1, &n2, &n3], "fruits", "{apple, pear}");
+        wait_for(&[&n1, &n2, &n3], "votes", "{yes: 2}");
+
+        run(&n3, "remove fruits apple");
+        run(&n1, "delete votes yes");
+        wait_for(&[&n1, &n2, &n3], "fruits", "{pear}");
+        wait_for(&[&n1, &n2, &n3], "votes", "{}");
+    }
+
+    #[test]
+    fn reports_deltas_it_cannot_merge() {
+        let n1 = start(REPLICA_1);
+        let n2 = start(REPLICA_2);
+        let errors = n1.errors();
+
+        run(&n1, "add x foo");
+        run(&n2, "inc x");
+        n1.connect(n2.local_addr());
+
+        let err = errors.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(
+            err,
+            Error::WrongKind {
+                name: "x".to_owned(),
+                kind: Kind::Set
+            }
+        );
+    }
+}
@@ -0,0 +1,406 @@
+//! Named CRDTs hosted by a node. Objects are created on first use, either
+//! by a local update or by a delta received from a peer.
+
+use rust_crdt_examples::state_crdt::{AWORMap, AWORMapDelta, AWORSet, DotKernel, PNCounter};
+use rust_crdt_examples::vclock::VClock;
+use rust_crdt_examples::{Convergent, DeltaSince, ReplicaId};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub type Counter = PNCounter;
+pub type Set = AWORSet<String>;
+/// Map of counters, e.g. votes per option
+pub type Map = AWORMap<String, PNCounter>;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Kind {
+    Counter,
+    Set,
+    Map,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Counter => write!(f, "counter"),
+            Self::Set => write!(f, "set"),
+            Self::Map => write!(f, "map"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Object {
+    Counter(Counter),
+    Set(Set),
+    Map(Map),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ObjectDelta {
+    Counter(Counter),
+    Set(DotKernel<String>),
+    Map(AWORMapDelta<String, PNCounter>),
+}
+
+impl ObjectDelta {
+    pub fn kind(&self) -> Kind {
+        match self {
+            Self::Counter(_) => Kind::Counter,
+            Self::Set(_) => Kind::Set,
+            Self::Map(_) => Kind::Map,
+        }
+    }
+}
+
+impl Object {
+    pub fn new(kind: Kind, replica_id: ReplicaId) -> Self {
+        match kind {
+            Kind::Counter => Self::Counter(Counter::new()),
+            Kind::Set => Self::Set(Set::new(replica_id)),
+            Kind::Map => Self::Map(Map::new(replica_id)),
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        match self {
+            Self::Counter(_) => Kind::Counter,
+            Self::Set(_) => Kind::Set,
+            Self::Map(_) => Kind::Map,
+        }
+    }
+
+    pub fn clock(&self) -> VClock {
+        match self {
+            Self::Counter(c) => c.clock(),
+            Self::Set(s) => s.clock(),
+            Self::Map(m) => m.clock(),
+        }
+    }
+
+    pub fn delta_since(&self, clock: &VClock) -> Option<ObjectDelta> {
+        match self {
+            Self::Counter(c) => c.delta_since(clock).map(ObjectDelta::Counter),
+            Self::Set(s) => s.delta_since(clock).map(ObjectDelta::Set),
+            Self::Map(m) => m.delta_since(clock).map(ObjectDelta::Map),
+        }
+    }
+
+    pub fn take_delta(&mut self) -> Option<ObjectDelta> {
+        match self {
+            Self::Counter(c) => c.take_delta().map(ObjectDelta::Counter),
+            Self::Set(s) => s.take_delta().map(ObjectDelta::Set),
+            Self::Map(m) => m.take_delta().map(ObjectDelta::Map),
+        }
+    }
+
+    /// Merges the delta, which has to be of the same kind as the object
+    pub fn merge_delta(&mut self, delta: ObjectDelta) -> bool {
+        match (self, delta) {
+            (Self::Counter(c), ObjectDelta::Counter(d)) => c.merge_delta(d),
+            (Self::Set(s), ObjectDelta::Set(d)) => s.merge_delta(d),
+            (Self::Map(m), ObjectDelta::Map(d)) => m.merge_delta(d),
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Renders the value of the object, elements and keys in sorted order
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Counter(c) => write!(f, "{}", c.value()),
+            Self::Set(s) => {
+                let mut keys: Vec<_> = s.keys().collect();
+                keys.sort();
+                write!(f, "{{{}}}", join(keys))
+            }
+            Self::Map(m) => {
+                let mut entries: Vec<_> = m.iter().collect();
+                entries.sort_by_key(|(k, _)| *k);
+                let entries = entries.iter().map(|(k, v)| format!("{}: {}", k, v.value()));
+                write!(f, "{{{}}}", join(entries))
+            }
+        }
+    }
+}
+
+fn join<T: ToString>(items: impl IntoIterator<Item = T>) -> String {
+    items
+        .into_iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Error {
+    /// The object exists but is of another kind than the operation needs
+    WrongKind {
+        name: String,
+        kind: Kind,
+    },
+    UnknownObject(String),
+    Overflow,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongKind { name, kind } => write!(f, "{} is a {}", name, kind),
+            Self::UnknownObject(name) => write!(f, "no such object: {}", name),
+            Self::Overflow => write!(f, "counter overflow"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug)]
+pub struct Replicas {
+    replica_id: ReplicaId,
+    objects: BTreeMap<String, Object>,
+}
+
+impl Replicas {
+    pub fn new(replica_id: ReplicaId) -> Self {
+        Self {
+            replica_id,
+            objects: BTreeMap::new(),
+        }
+    }
+
+    pub fn replica_id(&self) -> ReplicaId {
+        self.replica_id
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Object, Error> {
+        self.objects
+            .get(name)
+            .ok_or_else(|| Error::UnknownObject(name.to_owned()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Object)> {
+        self.objects.iter()
+    }
+
+    /// Returns the object, creating it if missing
+    pub fn get_or_create(&mut self, name: &str, kind: Kind) -> Result<&mut Object, Error> {
+        let replica_id = self.replica_id;
+        let object = self
+            .objects
+            .entry(name.to_owned())
+            .or_insert_with(|| Object::new(kind, replica_id));
+
+        check_kind(name, object, kind)?;
+        Ok(object)
+    }
+
+    /// Runs the update on the counter, returning the delta to send to peers
+    pub fn update_counter<F>(&mut self, name: &str, f: F) -> Result<ObjectDelta, Error>
+    where
+        F: FnOnce(&mut Counter, ReplicaId) -> Result<(), Error>,
+    {
+        self.update(name, Kind::Counter, |object, replica_id| {
+            let Object::Counter(c) = object else {
+                unreachable!()
+            };
+            f(c, replica_id)?;
+            Ok(ObjectDelta::Counter(c.take_delta().unwrap()))
+        })
+    }
+
+    pub fn update_set<F>(&mut self, name: &str, f: F) -> Result<Option<ObjectDelta>, Error>
+    where
+        F: FnOnce(&mut Set),
+    {
+        self.update(name, Kind::Set, |object, _| {
+            let Object::Set(s) = object else {
+                unreachable!()
+            };
+            f(s);
+            Ok(s.take_delta().map(ObjectDelta::Set))
+        })
+    }
+
+    pub fn update_map<F>(&mut self, name: &str, f: F) -> Result<Option<ObjectDelta>, Error>
+    where
+        F: FnOnce(&mut Map, ReplicaId) -> Result<(), Error>,
+    {
+        self.update(name, Kind::Map, |object, replica_id| {
+            let Object::Map(m) = object else {
+                unreachable!()
+            };
+            f(m, replica_id)?;
+            Ok(m.take_delta().map(ObjectDelta::Map))
+        })
+    }
+
+    /// Runs the update on the object, a missing one
+    /// is only created if the update succeeds
+    fn update<T, F>(&mut self, name: &str, kind: Kind, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Object, ReplicaId) -> Result<T, Error>,
+    {
+        let replica_id = self.replica_id;
+        if let Some(object) = self.objects.get_mut(name) {
+            check_kind(name, object, kind)?;
+            return f(object, replica_id);
+        }
+
+        let mut object = Object::new(kind, replica_id);
+        let result = f(&mut object, replica_id)?;
+        self.objects.insert(name.to_owned(), object);
+        Ok(result)
+    }
+
+    /// Returns the version vectors of all objects, sent to peers
+    /// so they can reply with whatever this node is missing
+    pub fn clocks(&self) -> BTreeMap<String, VClock> {
+        self.objects
+            .iter()
+            .map(|(name, object)| (name.clone(), object.clock()))
+            .collect()
+    }
+
+    /// Returns the deltas a node with the given clocks is missing,
+    /// objects it doesn't know about are sent in full
+    pub fn deltas_since(&self, clocks: &BTreeMap<String, VClock>) -> Vec<(String, ObjectDelta)> {
+        let empty = VClock::new();
+        self.objects
+            .iter()
+            .filter_map(|(name, object)| {
+                let clock = clocks.get(name).unwrap_or(&empty);
+                object.delta_since(clock).map(|d| (name.clone(), d))
+            })
+            .collect()
+    }
+
+    pub fn merge_delta(&mut self, name: &str, delta: ObjectDelta) -> Result<(), Error> {
+        let object = self.get_or_create(name, delta.kind())?;
+        object.merge_delta(delta);
+        // local updates take their deltas right away, what is left is
+        // only the received one, which peers get from its origin
+        object.take_delta();
+        Ok(())
+    }
+}
+
+fn check_kind(name: &str, object: &Object, kind: Kind) -> Result<(), Error> {
+    if object.kind() == kind {
+        Ok(())
+    } else {
+        Err(Error::WrongKind {
+            name: name.to_owned(),
+            kind: object.kind(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    fn sync(from: &Replicas, to: &mut Replicas) {
+        for (name, delta) in from.deltas_since(&to.clocks()) {
+            to.merge_delta(&name, delta).unwrap();
+        }
+    }
+
+    fn render(replicas: &Replicas, name: &str) -> String {
+        replicas.get(name).unwrap().to_string()
+    }
+
+    #[test]
+    fn catch_up_and_converge() {
+        let mut r1 = Replicas::new(REPLICA_1);
+        let mut r2 = Replicas::new(REPLICA_2);
+
+        r1.update_counter("hits", |c, id| c.add(id, 3).map_err(|_| Error::Overflow))
+            .unwrap();
+        r1.update_set("fruits", |s| s.add("apple".to_owned()))
+            .unwrap();
+        r2.update_set("fruits", |s| s.add("pear".to_owned()))
+            .unwrap();
+        r2.update_map("votes", |m, id| {
+            m.update("yes".to_owned(), |c| c.inc(id));
+            Ok(())
+        })
+        .unwrap();
+
+        sync(&r1, &mut r2);
+        sync(&r2, &mut r1);
+
+        for r in [&r1, &r2] {
+            assert_eq!(render(r, "hits"), "3");
+            assert_eq!(render(r, "fruits"), "{apple, pear}");
+            assert_eq!(render(r, "votes"), "{yes: 1}");
+        }
+
+        // deltas sent right after local updates apply as well
+        let delta = r2.update_set("fruits", |s| s.remove("apple")).unwrap();
+        r1.merge_delta("fruits", delta.unwrap()).unwrap();
+        assert_eq!(render(&r1, "fruits"), "{pear}");
+    }
+
+    #[test]
+    fn catch_up_on_removals() {
+        let mut r1 = Replicas::new(REPLICA_1);
+        let mut r2 = Replicas::new(REPLICA_2);
+
+        r1.update_set("fruits", |s| s.add("apple".to_owned()))
+            .unwrap();
+        r1.update_map("votes", |m, id| {
+            m.update("yes".to_owned(), |c| c.inc(id));
+            m.update("no".to_owned(), |c| c.inc(id));
+            Ok(())
+        })
+        .unwrap();
+        sync(&r1, &mut r2);
+
+        r1.update_set("fruits", |s| s.remove("apple")).unwrap();
+        r1.update_map("votes", |m, _| {
+            m.remove("no");
+            Ok(())
+        })
+        .unwrap();
+        sync(&r1, &mut r2);
+
+        assert_eq!(render(&r2, "fruits"), "{}");
+        assert_eq!(render(&r2, "votes"), "{yes: 1}");
+        assert!(r1.deltas_since(&r2.clocks()).is_empty());
+        assert!(r2.deltas_since(&r1.clocks()).is_empty());
+    }
+
+    #[test]
+    fn kind_mismatch() {
+        let mut r1 = Replicas::new(REPLICA_1);
+        r1.update_set("x", |s| s.add("foo".to_owned())).unwrap();
+
+        let err = r1.update_counter("x", |_, _| Ok(())).unwrap_err();
+        assert_eq!(
+            err,
+            Error::WrongKind {
+                name: "x".to_owned(),
+                kind: Kind::Set
+            }
+        );
+        assert_eq!(err.to_string(), "x is a set");
+
+        let delta = ObjectDelta::Counter(Counter::new());
+        assert!(r1.merge_delta("x", delta).is_err());
+
+        // failed updates don't leave new objects behind
+        let err = r1.update_counter("y", |_, _| Err(Error::Overflow));
+        assert_eq!(err.unwrap_err(), Error::Overflow);
+        assert_eq!(
+            r1.get("y").unwrap_err(),
+            Error::UnknownObject("y".to_owned())
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Runs several `crdt-node` processes on localhost and checks they converge
+
+use std::io::{BufRead, BufReader, Lines, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    addr: String,
+}
+
+impl Process {
+    fn spawn(id: u64, peers: &[&str]) -> Self {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_crdt-node"));
+        cmd.args(["--id", &id.to_string(), "--sync-interval", "50"]);
+        for peer in peers {
+            cmd.args(["--peer", peer]);
+        }
+
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+
+        let line = stdout.next().unwrap().unwrap();
+        let addr = line.strip_prefix("listening on ").unwrap().to_owned();
+
+        Self {
+            child,
+            stdin,
+            stdout,
+            addr,
+        }
+    }
+
+    fn run(&mut self, line: &str) -> String {
+        writeln!(self.stdin, "{}", line).unwrap();
+        self.stdout.next().unwrap().unwrap()
+    }
+
+    fn wait_for(&mut self, name: &str, expected: &str) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let value = self.run(&format!("get {}", name));
+            if value == expected {
+                return;
+            }
+            assert!(Instant::now() < deadline, "{} is {}", name, value);
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn processes_converge() {
+    let mut p1 = Process::spawn(1, &[]);
+    let mut p2 = Process::spawn(2, &[&p1.addr]);
+    let mut p3 = Process::spawn(3, &[&p1.addr, &p2.addr]);
+
+    assert_eq!(p1.run("inc hits 5"), "ok");
+    assert_eq!(p2.run("dec hits 2"), "ok");
+    assert_eq!(p3.run("add fruits apple"), "ok");
+    assert_eq!(p1.run("add fruits pear"), "ok");
+    assert_eq!(p2.run("incr votes yes"), "ok");
+    assert_eq!(p3.run("incr votes no 2"), "ok");
+    assert_eq!(p1.run("add hits foo"), "error: hits is a counter");
+
+    for p in [&mut p1, &mut p2, &mut p3] {
+        p.wait_for("hits", "3");
+        p.wait_for("fruits", "{apple, pear}");
+        p.wait_for("votes", "{no: 2, yes: 1}");
+    }
+
+    // a node joining later catches up, including updates made while
+    // another one is down, replica ids are not reused as p3 lost its state
+    drop(p3);
+    p2.run("remove fruits apple");
+    let mut p4 = Process::spawn(4, &[&p1.addr]);
+    p4.wait_for("fruits", "{pear}");
+    p4.wait_for("hits", "3");
+
+    writeln!(p4.stdin, "quit").unwrap();
+    assert!(p4.child.wait().unwrap().success());
+}
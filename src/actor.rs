@@ -0,0 +1,330 @@
+//! Replica running as a tokio task which owns the state.
+//!
+//! Local updates go through a [`ReplicaHandle`], and the deltas they produce
+//! come out of the outbound channel, to be shipped to other replicas by
+//! whatever transport the application uses. Deltas and states received from
+//! other replicas are fed into the inbound channel. Deltas may get lost on
+//! the way, so every anti-entropy interval the actor also sends out its full
+//! state, which brings lagging replicas up to date.
+//!
+//! The actor never waits for the outbound channel. While it is full, deltas
+//! of local updates pile up in the state and go out as one once it has room,
+//! and anti-entropy rounds are skipped.
+
+use crate::Convergent;
+use core::fmt;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{self, MissedTickBehavior};
+
+/// Message exchanged between replicas
+#[derive(Debug, Clone)]
+pub enum Message<T: Convergent> {
+    Delta(T::Delta),
+    /// Full state sent on anti-entropy rounds
+    State(T),
+}
+
+/// Error returned by the handle once the actor has stopped
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ActorStopped;
+
+impl fmt::Display for ActorStopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "replica actor has stopped")
+    }
+}
+
+impl std::error::Error for ActorStopped {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ActorOptions {
+    /// How often the full state is sent out
+    pub anti_entropy_interval: Duration,
+    /// Capacity of each channel, deltas are held back while the
+    /// outbound channel is full and full states are dropped
+    pub channel_capacity: usize,
+}
+
+impl Default for ActorOptions {
+    fn default() -> Self {
+        Self {
+            anti_entropy_interval: Duration::from_secs(10),
+            channel_capacity: 64,
+        }
+    }
+}
+
+enum Request<T> {
+    Update(Box<dyn FnOnce(&mut T) + Send>),
+    Read(Box<dyn FnOnce(&T) + Send>),
+}
+
+/// Handle for reading and updating the state owned by the actor, cheap to clone
+pub struct ReplicaHandle<T> {
+    tx: mpsc::Sender<Request<T>>,
+}
+
+impl<T> Clone for ReplicaHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<T: Send + 'static> ReplicaHandle<T> {
+    /// Applies the function to the state and returns its result. The delta
+    /// produced by the update is sent out before the next one is applied,
+    /// unless the outbound channel is full.
+    pub async fn update<F, R>(&self, f: F) -> Result<R, ActorStopped>
+    where
+        F: FnOnce(&mut T) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let update = Box::new(move |state: &mut T| {
+            let _ = tx.send(f(state));
+        });
+        self.request(Request::Update(update), rx).await
+    }
+
+    /// Applies the function to the current state and returns its result
+    pub async fn read<F, R>(&self, f: F) -> Result<R, ActorStopped>
+    where
+        F: FnOnce(&T) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let read = Box::new(move |state: &T| {
+            let _ = tx.send(f(state));
+        });
+        self.request(Request::Read(read), rx).await
+    }
+
+    async fn request<R>(
+        &self,
+        req: Request<T>,
+        rx: oneshot::Receiver<R>,
+    ) -> Result<R, ActorStopped> {
+        self.tx.send(req).await.map_err(|_| ActorStopped)?;
+        rx.await.map_err(|_| ActorStopped)
+    }
+}
+
+/// Channels of a spawned replica actor
+pub struct ReplicaActor<T: Convergent> {
+    pub handle: ReplicaHandle<T>,
+    /// Deltas and states received from other replicas
+    pub inbound: mpsc::Sender<Message<T>>,
+    /// Deltas of local updates and periodic full states
+    pub outbound: mpsc::Receiver<Message<T>>,
+    /// Resolves to the final state once all handles and inbound senders
+    /// have been dropped, along with the deltas which weren't sent out
+    pub task: JoinHandle<T>,
+}
+
+impl<T> ReplicaActor<T>
+where
+    T: Convergent + Clone + Send + 'static,
+    T::Delta: Send,
+{
+    /// Spawns the actor on the current tokio runtime
+    pub fn spawn(state: T, options: ActorOptions) -> Self {
+        let (handle_tx, handle_rx) = mpsc::channel(options.channel_capacity);
+        let (inbound_tx, inbound_rx) = mpsc::channel(options.channel_capacity);
+        let (outbound_tx, outbound_rx) = mpsc::channel(options.channel_capacity);
+
+        let task = tokio::spawn(run(state, options, handle_rx, inbound_rx, outbound_tx));
+
+        Self {
+            handle: ReplicaHandle { tx: handle_tx },
+            inbound: inbound_tx,
+            outbound: outbound_rx,
+            task,
+        }
+    }
+}
+
+async fn run<T>(
+    mut state: T,
+    options: ActorOptions,
+    mut requests: mpsc::Receiver<Request<T>>,
+    mut inbound: mpsc::Receiver<Message<T>>,
+    outbound: mpsc::Sender<Message<T>>,
+) -> T
+where
+    T: Convergent + Clone,
+{
+    let mut anti_entropy = time::interval(options.anti_entropy_interval);
+    anti_entropy.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // the first tick completes right away
+    anti_entropy.tick().await;
+
+    let (mut requests_open, mut inbound_open) = (true, true);
+    // whether the delta of the state holds local updates not sent yet
+    let mut pending = false;
+
+    while requests_open || inbound_open {
+        tokio::select! {
+            // received messages go first, so updates and reads queued
+            // after a message was delivered see its effect
+            biased;
+
+            msg = inbound.recv(), if inbound_open => match msg {
+                Some(msg) => {
+                    match msg {
+                        Message::Delta(delta) => state.merge_delta(delta),
+                        Message::State(other) => state.merge(other),
+                    }
+                    // what came from the other replica is shipped by it, unless
+                    // it is mixed with local deltas waiting for the channel
+                    if !pending {
+                        state.take_delta();
+                    }
+                }
+                None => inbound_open = false,
+            },
+            permit = outbound.reserve(), if pending => {
+                // a closed outbound channel only means nobody listens
+                if let (Ok(permit), Some(delta)) = (permit, state.take_delta()) {
+                    permit.send(Message::Delta(delta));
+                }
+                pending = false;
+            },
+            req = requests.recv(), if requests_open => match req {
+                Some(Request::Update(update)) => {
+                    update(&mut state);
+                    pending = true;
+                }
+                Some(Request::Read(read)) => read(&state),
+                None => requests_open = false,
+            },
+            _ = anti_entropy.tick() => {
+                // rounds finding the channel full are skipped,
+                // the next one sends a newer state anyway
+                if let Ok(permit) = outbound.try_reserve() {
+                    permit.send(Message::State(state.clone()));
+                }
+            }
+        }
+    }
+
+    state
+}
+
+#[cfg(all(test, feature = "sets"))]
+mod tests {
+    use super::*;
+    use crate::state_crdt::AWORSet;
+    use crate::ReplicaId;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    type Set = AWORSet<&'static str>;
+
+    fn spawn(replica_id: ReplicaId) -> ReplicaActor<Set> {
+        let options = ActorOptions {
+            anti_entropy_interval: Duration::from_secs(1),
+            channel_capacity: 16,
+        };
+        ReplicaActor::spawn(AWORSet::new(replica_id), options)
+    }
+
+    async fn keys(handle: &ReplicaHandle<Set>) -> Vec<&'static str> {
+        let mut keys = handle
+            .read(|s| s.keys().copied().collect::<Vec<_>>())
+            .await
+            .unwrap();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deltas_flow_between_replicas() {
+        let mut a = spawn(REPLICA_1);
+        let b = spawn(REPLICA_2);
+
+        let len = a
+            .handle
+            .update(|s| {
+                s.add("foo");
+                s.add("bar");
+                s.keys().count()
+            })
+            .await
+            .unwrap();
+        assert_eq!(len, 2);
+        a.handle.update(|s| s.remove("foo")).await.unwrap();
+
+        // both updates come out as deltas before any anti-entropy round
+        for _ in 0..2 {
+            let msg = a.outbound.recv().await.unwrap();
+            assert!(matches!(msg, Message::Delta(_)));
+            b.inbound.send(msg).await.unwrap();
+        }
+        assert_eq!(keys(&b.handle).await, vec!["bar"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn anti_entropy_repairs_lost_deltas() {
+        let mut a = spawn(REPLICA_1);
+        let b = spawn(REPLICA_2);
+
+        a.handle.update(|s| s.add("foo")).await.unwrap();
+        // the delta gets lost on the way
+        assert!(matches!(a.outbound.recv().await, Some(Message::Delta(_))));
+        assert!(keys(&b.handle).await.is_empty());
+
+        let start = time::Instant::now();
+        let msg = a.outbound.recv().await.unwrap();
+        assert!(matches!(msg, Message::State(_)));
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+        b.inbound.send(msg).await.unwrap();
+        assert_eq!(keys(&b.handle).await, vec!["foo"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn full_outbound_channel() {
+        let options = ActorOptions {
+            anti_entropy_interval: Duration::from_secs(1),
+            channel_capacity: 1,
+        };
+        let mut a = ReplicaActor::spawn(AWORSet::new(REPLICA_1), options);
+        let b = spawn(REPLICA_2);
+
+        // nobody reads the outbound channel for a few anti-entropy rounds
+        for key in ["foo", "bar", "baz"] {
+            a.handle.update(move |s| s.add(key)).await.unwrap();
+        }
+        time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(keys(&a.handle).await, vec!["bar", "baz", "foo"]);
+
+        // the first delta took the only slot, the rest
+        // went out as one as soon as there was room
+        for _ in 0..2 {
+            let msg = a.outbound.recv().await.unwrap();
+            assert!(matches!(msg, Message::Delta(_)));
+            b.inbound.send(msg).await.unwrap();
+        }
+        assert_eq!(keys(&b.handle).await, vec!["bar", "baz", "foo"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_once_all_senders_are_dropped() {
+        let a = spawn(REPLICA_1);
+        let handle = a.handle.clone();
+        handle.update(|s| s.add("foo")).await.unwrap();
+
+        drop(a.handle);
+        drop(a.inbound);
+        assert!(!a.task.is_finished());
+
+        drop(handle);
+        let state = a.task.await.unwrap();
+        assert!(state.contains("foo"));
+    }
+}
@@ -0,0 +1,9 @@
+//! Collections backing the data types: the `std` hash maps when the `std`
+//! feature is enabled, `hashbrown` ones with its default hasher otherwise
+
+pub use alloc::collections::{BTreeMap, BTreeSet};
+
+#[cfg(not(feature = "std"))]
+pub use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "std")]
+pub use std::collections::{HashMap, HashSet};
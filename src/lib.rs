@@ -0,0 +1,33 @@
+//! State-based and delta-state CRDTs along with the logical clocks they
+//! are built on.
+//!
+//! Data types are grouped behind cargo features, all enabled by default:
+//!
+//! - `counters`: [`GCounter`](state_crdt::GCounter), [`PNCounter`](state_crdt::PNCounter),
+//!   bounded and resettable counters
+//! - `registers`: max and min registers
+//! - `sets`: grow-only, two-phase and add-wins sets, observed-remove bag
+//! - `maps`: add-wins maps and the leaderboard, implies `sets` and `registers`
+//! - `hlc`: hybrid logical clock in [`vclock`]
+//! - `serde`: `Serialize`/`Deserialize` for all the types above
+//! - `storage`: snapshot and write-ahead log persistence on the local filesystem,
+//!   and add-wins maps paging their values out to a file
+//! - `tokio`: [`actor`] running a replica as a task which exchanges deltas over channels
+//!
+//! Without the default `std` feature the crate is `no_std` and only needs
+//! `alloc`, hash maps then come from `hashbrown`, see [`collections`].
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "tokio")]
+pub mod actor;
+pub mod collections;
+pub mod state_crdt;
+#[cfg(feature = "storage")]
+pub mod storage;
+pub mod vclock;
+
+pub use state_crdt::{Convergent, DeltaSince};
+pub use vclock::ReplicaId;
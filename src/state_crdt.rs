@@ -1,6 +1,10 @@
+pub mod causal;
+#[cfg(any(feature = "counters", feature = "registers", feature = "sets"))]
 mod types;
 
 pub use crate::vclock::ReplicaId;
+use crate::vclock::VClock;
+#[cfg(any(feature = "counters", feature = "registers", feature = "sets"))]
 pub use types::*;
 
 pub trait Convergent {
@@ -11,4 +15,32 @@ pub trait Convergent {
     fn merge_delta(&mut self, delta: Self::Delta);
 
     fn take_delta(&mut self) -> Option<Self::Delta>;
+
+    /// Returns a delta removing everything this replica has observed while
+    /// keeping concurrent updates, which maps ship when the value is removed.
+    /// Types whose deltas can't express removals return `None`, maps reset
+    /// such values as a whole once all updates of the key have been seen.
+    fn removal_delta(&self) -> Option<Self::Delta> {
+        None
+    }
+}
+
+/// Types created on behalf of a replica, such as the values maps create
+/// for keys they update or merge. Types which don't keep a replica id
+/// are created through `Default`.
+pub trait FromReplica {
+    fn from_replica(replica_id: ReplicaId) -> Self;
+}
+
+impl<T: Default> FromReplica for T {
+    fn from_replica(_replica_id: ReplicaId) -> Self {
+        T::default()
+    }
+}
+
+/// Convergent types which can tell what a replica at a given
+/// version vector is missing, e.g. to catch up a reconnecting peer
+pub trait DeltaSince: Convergent {
+    /// Returns a delta with the updates not covered by the clock
+    fn delta_since(&self, clock: &VClock) -> Option<Self::Delta>;
 }
@@ -0,0 +1,616 @@
+//! Building blocks for causal CRDTs as described in "Delta State
+//! Replicated Data Types" by Almeida, Shoker and Baquero.
+//!
+//! A causal CRDT is a pair of a [`DotStore`], which maps events
+//! (dots) to the payload they produced, and a [`DotContext`], which
+//! remembers every dot the replica has ever seen. A dot present in the
+//! context but missing from the store was removed, which lets [`Causal::join`]
+//! tell removals apart from not yet delivered additions.
+
+use super::ReplicaId;
+use crate::collections::{BTreeMap, HashMap, HashSet};
+use crate::vclock::VClock;
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::Hash;
+
+/// Unique identifier of an event: the replica which produced it
+/// and the sequence number of the event on that replica
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dot(pub ReplicaId, pub usize);
+
+impl fmt::Display for Dot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {})", self.0, self.1)
+    }
+}
+
+/// Set of sequence numbers stored as disjoint, non-adjacent
+/// inclusive ranges keyed by their start
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct RangeSet(BTreeMap<usize, usize>);
+
+impl RangeSet {
+    fn contains(&self, n: usize) -> bool {
+        self.0
+            .range(..=n)
+            .next_back()
+            .is_some_and(|(_, end)| *end >= n)
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(|(start, end)| end - start + 1).sum()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().flat_map(|(start, end)| *start..=*end)
+    }
+
+    fn max(&self) -> usize {
+        self.0.last_key_value().map_or(0, |(_, end)| *end)
+    }
+
+    fn insert(&mut self, start: usize, end: usize) {
+        let (mut start, mut end) = (start, end);
+
+        // extend the preceding range if it overlaps or is adjacent
+        if let Some((s, e)) = self.0.range(..=start).next_back() {
+            if *e >= end {
+                return;
+            }
+            if e.saturating_add(1) >= start {
+                start = *s;
+            }
+        }
+
+        // absorb all the following ranges it overlaps or is adjacent to
+        while let Some((s, e)) = self.0.range(start..=end.saturating_add(1)).next() {
+            let s = *s;
+            end = end.max(*e);
+            self.0.remove(&s);
+        }
+
+        self.0.insert(start, end);
+    }
+
+    fn remove(&mut self, n: usize) {
+        let Some((start, end)) = self.0.range(..=n).next_back().map(|(s, e)| (*s, *e)) else {
+            return;
+        };
+        if end < n {
+            return;
+        }
+
+        self.0.remove(&start);
+        if start < n {
+            self.0.insert(start, n - 1);
+        }
+        if n < end {
+            self.0.insert(n + 1, end);
+        }
+    }
+
+    /// Returns the end of the range starting at 1, i.e. the highest
+    /// sequence number below which there are no gaps
+    fn prefix(&self) -> usize {
+        self.0.get(&1).copied().unwrap_or(0)
+    }
+}
+
+/// Causal context: the set of all dots observed by a replica
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DotContext(HashMap<ReplicaId, RangeSet>);
+
+impl DotContext {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the number of dots in the context
+    pub fn len(&self) -> usize {
+        self.0.values().map(|r| r.len()).sum()
+    }
+
+    pub fn dots(&self) -> impl Iterator<Item = Dot> + '_ {
+        self.0
+            .iter()
+            .flat_map(|(replica, r)| r.iter().map(|n| Dot(*replica, n)))
+    }
+
+    pub fn contains(&self, dot: &Dot) -> bool {
+        self.0.get(&dot.0).is_some_and(|r| r.contains(dot.1))
+    }
+
+    /// Returns whether every dot of the context is covered by the clock
+    pub fn covered_by(&self, clock: &VClock) -> bool {
+        self.0
+            .iter()
+            .all(|(replica, r)| r.max() <= clock.get(replica))
+    }
+
+    /// Generates the next dot for the replica and records it as seen
+    pub fn next_dot(&mut self, replica: ReplicaId) -> Dot {
+        let ranges = self.0.entry(replica).or_default();
+        let n = ranges.max() + 1;
+        ranges.insert(n, n);
+        Dot(replica, n)
+    }
+
+    /// Like [`next_dot`](Self::next_dot), but returns `None` once
+    /// the counter of the replica can't go any higher
+    pub fn checked_next_dot(&mut self, replica: ReplicaId) -> Option<Dot> {
+        let ranges = self.0.entry(replica).or_default();
+        let n = ranges.max().checked_add(1)?;
+        ranges.insert(n, n);
+        Some(Dot(replica, n))
+    }
+
+    pub fn add(&mut self, dot: Dot) {
+        self.0.entry(dot.0).or_default().insert(dot.1, dot.1);
+    }
+
+    /// Forgets the dot, only meant for building deltas
+    pub fn remove(&mut self, dot: &Dot) {
+        if let Some(ranges) = self.0.get_mut(&dot.0) {
+            ranges.remove(dot.1);
+            if ranges.0.is_empty() {
+                self.0.remove(&dot.0);
+            }
+        }
+    }
+
+    /// Returns the version vector of the context: for every replica the
+    /// sequence number up to which all of its dots have been seen. Dots
+    /// received out of order past a gap are left out.
+    pub fn clock(&self) -> VClock {
+        self.0
+            .iter()
+            .map(|(replica, r)| (*replica, r.prefix()))
+            .filter(|(_, n)| *n > 0)
+            .collect()
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        for (replica, ranges) in other.0 {
+            let self_ranges = self.0.entry(replica).or_default();
+            for (start, end) in ranges.0 {
+                self_ranges.insert(start, end);
+            }
+        }
+    }
+}
+
+/// Formats the context as `{replica: [ranges], ...}` ordered by replica,
+/// e.g. `{1: [1..=4, 7], 2: [1..=2]}`
+impl fmt::Display for DotContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut replicas: Vec<_> = self.0.iter().collect();
+        replicas.sort_by_key(|(replica, _)| **replica);
+
+        write!(f, "{{")?;
+        for (i, (replica, ranges)) in replicas.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: [", replica)?;
+            for (j, (start, end)) in ranges.0.iter().enumerate() {
+                if j > 0 {
+                    write!(f, ", ")?;
+                }
+                if start == end {
+                    write!(f, "{}", start)?;
+                } else {
+                    write!(f, "{}..={}", start, end)?;
+                }
+            }
+            write!(f, "]")?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// Container of dots which can be joined under a pair of causal contexts
+pub trait DotStore: Default {
+    fn is_empty(&self) -> bool;
+
+    /// Iterates over all dots present in the store
+    fn dots(&self) -> impl Iterator<Item = Dot> + '_;
+
+    /// Joins the other store into this one, keeping dots present in both
+    /// stores and dots unseen by the other context, and dropping dots
+    /// the other context has seen but the other store no longer has
+    fn join(&mut self, context: &DotContext, other: Self, other_context: &DotContext);
+}
+
+/// Plain set of dots, e.g. for an enable-wins flag
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DotSet(pub HashSet<Dot>);
+
+impl DotStore for DotSet {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn dots(&self) -> impl Iterator<Item = Dot> + '_ {
+        self.0.iter().copied()
+    }
+
+    fn join(&mut self, context: &DotContext, other: Self, other_context: &DotContext) {
+        if other_context.len() < self.0.len() {
+            for dot in other_context.dots() {
+                if !other.0.contains(&dot) {
+                    self.0.remove(&dot);
+                }
+            }
+        } else {
+            self.0
+                .retain(|dot| other.0.contains(dot) || !other_context.contains(dot));
+        }
+
+        for dot in other.0 {
+            if !context.contains(&dot) {
+                self.0.insert(dot);
+            }
+        }
+    }
+}
+
+/// Map from dots to the values written by them. Values bound
+/// to a dot are immutable, so for dots present in both stores
+/// the current value is kept.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DotFun<V>(pub HashMap<Dot, V>);
+
+impl<V> Default for DotFun<V> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+impl<V> DotStore for DotFun<V> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn dots(&self) -> impl Iterator<Item = Dot> + '_ {
+        self.0.keys().copied()
+    }
+
+    fn join(&mut self, context: &DotContext, other: Self, other_context: &DotContext) {
+        // look for removed dots on whichever side is smaller
+        if other_context.len() < self.0.len() {
+            for dot in other_context.dots() {
+                if !other.0.contains_key(&dot) {
+                    self.0.remove(&dot);
+                }
+            }
+        } else {
+            self.0
+                .retain(|dot, _| other.0.contains_key(dot) || !other_context.contains(dot));
+        }
+
+        for (dot, v) in other.0 {
+            if !context.contains(&dot) {
+                self.0.entry(dot).or_insert(v);
+            }
+        }
+    }
+}
+
+/// Map from keys to nested dot stores sharing the same causal context
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "K: serde::Serialize + Eq + Hash, S: serde::Serialize",
+        deserialize = "K: serde::Deserialize<'de> + Eq + Hash, S: serde::Deserialize<'de>"
+    ))
+)]
+pub struct DotMap<K, S>(pub HashMap<K, S>);
+
+impl<K, S> Default for DotMap<K, S> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+impl<K: Eq + Hash, S: PartialEq> PartialEq for DotMap<K, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<K: Eq + Hash, S: Eq> Eq for DotMap<K, S> {}
+
+impl<K: Eq + Hash, S: DotStore> DotStore for DotMap<K, S> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn dots(&self) -> impl Iterator<Item = Dot> + '_ {
+        self.0.values().flat_map(|s| s.dots())
+    }
+
+    fn join(&mut self, context: &DotContext, other: Self, other_context: &DotContext) {
+        let mut other = other;
+
+        for (k, v) in self.0.iter_mut() {
+            let v2 = other.0.remove(k).unwrap_or_default();
+            v.join(context, v2, other_context);
+        }
+
+        for (k, v2) in other.0 {
+            let mut v = S::default();
+            v.join(context, v2, other_context);
+            self.0.insert(k, v);
+        }
+
+        self.0.retain(|_, v| !v.is_empty());
+    }
+}
+
+/// Dot store paired with its causal context
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Causal<S> {
+    pub store: S,
+    pub context: DotContext,
+}
+
+impl<S: DotStore> Causal<S> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn join(&mut self, other: Self) {
+        self.store.join(&self.context, other.store, &other.context);
+        self.context.merge(other.context);
+    }
+
+    /// Returns a delta with an empty store and the whole context, which
+    /// removes every observed dot from the replicas joining it
+    pub fn removal(&self) -> Self {
+        Self {
+            store: S::default(),
+            context: self.context.clone(),
+        }
+    }
+}
+
+impl<V: Clone> Causal<DotFun<V>> {
+    /// Returns the entries whose dots are not covered by the clock, or
+    /// `None` if the clock covers the whole context. The context keeps
+    /// every dot but the covered ones still in the store, so the receiver
+    /// also drops the covered entries removed in the meantime. Removals
+    /// have to add a dot of their own to the context to be caught up on.
+    pub fn since(&self, clock: &VClock) -> Option<Self> {
+        if self.context.covered_by(clock) {
+            return None;
+        }
+
+        let mut delta = Self {
+            store: DotFun::default(),
+            context: self.context.clone(),
+        };
+
+        for (dot, v) in &self.store.0 {
+            if dot.1 <= clock.get(&dot.0) {
+                delta.context.remove(dot);
+            } else {
+                delta.store.0.insert(*dot, v.clone());
+            }
+        }
+
+        Some(delta)
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Causal<DotMap<K, DotFun<V>>> {
+    /// Like [`Causal::<DotFun<V>>::since`], for entries nested under keys
+    pub fn since(&self, clock: &VClock) -> Option<Self> {
+        if self.context.covered_by(clock) {
+            return None;
+        }
+
+        let mut delta = Self {
+            store: DotMap::default(),
+            context: self.context.clone(),
+        };
+
+        for (key, fun) in &self.store.0 {
+            for (dot, v) in &fun.0 {
+                if dot.1 <= clock.get(&dot.0) {
+                    delta.context.remove(dot);
+                } else {
+                    let entries = delta.store.0.entry(key.clone()).or_default();
+                    entries.0.insert(*dot, v.clone());
+                }
+            }
+        }
+
+        Some(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    #[test]
+    fn dot_context_at_max_counter() {
+        let mut ctx = DotContext::new();
+
+        ctx.add(Dot(REPLICA_1, usize::MAX - 1));
+        ctx.add(Dot(REPLICA_1, usize::MAX));
+        assert!(ctx.contains(&Dot(REPLICA_1, usize::MAX)));
+        assert_eq!(ctx.len(), 2);
+
+        let mut ctx = DotContext::new();
+        ctx.add(Dot(REPLICA_1, usize::MAX - 1));
+        assert_eq!(
+            ctx.checked_next_dot(REPLICA_1),
+            Some(Dot(REPLICA_1, usize::MAX))
+        );
+        assert_eq!(ctx.checked_next_dot(REPLICA_1), None);
+        assert_eq!(ctx.len(), 2);
+    }
+
+    #[test]
+    fn dot_context_out_of_order() {
+        let mut ctx = DotContext::new();
+
+        ctx.add(Dot(REPLICA_1, 5));
+        ctx.add(Dot(REPLICA_1, 3));
+        ctx.add(Dot(REPLICA_1, 1));
+        assert_eq!(ctx.0[&REPLICA_1].0.len(), 3);
+        assert!(ctx.contains(&Dot(REPLICA_1, 3)));
+        assert!(!ctx.contains(&Dot(REPLICA_1, 2)));
+        assert!(!ctx.contains(&Dot(REPLICA_1, 6)));
+
+        // filling the gaps collapses everything into a single range
+        ctx.add(Dot(REPLICA_1, 4));
+        ctx.add(Dot(REPLICA_1, 2));
+        assert_eq!(ctx.0[&REPLICA_1].0.len(), 1);
+        assert!((1..=5).all(|n| ctx.contains(&Dot(REPLICA_1, n))));
+
+        assert_eq!(ctx.next_dot(REPLICA_1), Dot(REPLICA_1, 6));
+        assert_eq!(ctx.next_dot(REPLICA_2), Dot(REPLICA_2, 1));
+    }
+
+    #[test]
+    fn dot_context_merge_ranges() {
+        let mut ctx1 = DotContext::new();
+        let mut ctx2 = DotContext::new();
+
+        for n in (1..=100).step_by(2) {
+            ctx1.add(Dot(REPLICA_1, n));
+            ctx2.add(Dot(REPLICA_1, n + 1));
+        }
+        ctx2.add(Dot(REPLICA_2, 7));
+
+        assert_eq!(ctx1.0[&REPLICA_1].0.len(), 50);
+
+        ctx1.merge(ctx2);
+
+        assert_eq!(ctx1.0[&REPLICA_1].0.len(), 1);
+        assert!(ctx1.contains(&Dot(REPLICA_1, 100)));
+        assert!(!ctx1.contains(&Dot(REPLICA_1, 101)));
+        assert!(ctx1.contains(&Dot(REPLICA_2, 7)));
+        assert!(!ctx1.contains(&Dot(REPLICA_2, 6)));
+    }
+
+    #[test]
+    fn dot_context_remove_and_clock() {
+        let mut ctx = DotContext::new();
+        for n in 1..=10 {
+            ctx.add(Dot(REPLICA_1, n));
+        }
+        ctx.add(Dot(REPLICA_2, 3));
+
+        ctx.remove(&Dot(REPLICA_1, 5));
+        ctx.remove(&Dot(REPLICA_1, 1));
+        ctx.remove(&Dot(REPLICA_1, 11));
+        assert_eq!(ctx.0[&REPLICA_1].0.len(), 2);
+        assert_eq!(ctx.len(), 9);
+        assert!(!ctx.contains(&Dot(REPLICA_1, 5)));
+        assert!(ctx.contains(&Dot(REPLICA_1, 6)));
+
+        // REPLICA_1 starts with a gap and REPLICA_2 hasn't delivered 1 and 2
+        assert_eq!(ctx.clock(), VClock::new());
+
+        ctx.add(Dot(REPLICA_1, 1));
+        ctx.add(Dot(REPLICA_2, 1));
+        let clock = ctx.clock();
+        assert_eq!(clock.get(&REPLICA_1), 4);
+        assert_eq!(clock.get(&REPLICA_2), 1);
+
+        assert_eq!(ctx.to_string(), "{123: [1..=4, 6..=10], 456: [1, 3]}");
+        assert_eq!(clock.to_string(), "{123: 4, 456: 1}");
+    }
+
+    #[test]
+    fn since_clock() {
+        let mut a: Causal<DotFun<u32>> = Causal::new();
+        let mut b = a.clone();
+
+        for i in 0..5 {
+            let dot = a.context.next_dot(REPLICA_1);
+            a.store.0.insert(dot, i);
+        }
+        b.join(a.clone());
+        let clock = b.context.clock();
+
+        // remove an entry b has seen and add a new one
+        a.store.0.remove(&Dot(REPLICA_1, 2));
+        let dot = a.context.next_dot(REPLICA_1);
+        a.store.0.insert(dot, 5);
+
+        let delta = a.since(&clock).unwrap();
+        assert_eq!(delta.store.0, HashMap::from([(dot, 5)]));
+        assert!(delta.context.contains(&Dot(REPLICA_1, 2)));
+        assert!(!delta.context.contains(&Dot(REPLICA_1, 1)));
+
+        b.join(delta);
+        assert_eq!(b.store, a.store);
+
+        assert!(a.since(&a.context.clock()).is_none());
+        assert_eq!(a.since(&VClock::new()), Some(a));
+    }
+
+    #[test]
+    fn dot_set_join() {
+        let mut a: Causal<DotSet> = Causal::new();
+        let mut b: Causal<DotSet> = Causal::new();
+
+        let d1 = a.context.next_dot(REPLICA_1);
+        a.store.0.insert(d1);
+        let d2 = b.context.next_dot(REPLICA_2);
+        b.store.0.insert(d2);
+
+        // b has seen d1 and removed it
+        b.context.add(d1);
+
+        a.join(b);
+
+        assert!(!a.store.0.contains(&d1));
+        assert!(a.store.0.contains(&d2));
+        assert!(a.context.contains(&d1) && a.context.contains(&d2));
+    }
+
+    #[test]
+    fn dot_map_join() {
+        let mut a: Causal<DotMap<&str, DotFun<u32>>> = Causal::new();
+        let mut b = a.clone();
+
+        let d1 = a.context.next_dot(REPLICA_1);
+        a.store.0.entry("foo").or_default().0.insert(d1, 1);
+
+        b.join(a.clone());
+        assert_eq!(b.store, a.store);
+
+        // b overwrites foo, a concurrently writes bar
+        let d2 = b.context.next_dot(REPLICA_2);
+        b.store.0.insert("foo", DotFun(HashMap::from([(d2, 2)])));
+        let d3 = a.context.next_dot(REPLICA_1);
+        a.store.0.entry("bar").or_default().0.insert(d3, 3);
+
+        a.join(b);
+
+        assert_eq!(a.store.0["foo"].0, HashMap::from([(d2, 2)]));
+        assert_eq!(a.store.0["bar"].0, HashMap::from([(d3, 3)]));
+        assert_eq!(a.store.dots().count(), 2);
+    }
+}
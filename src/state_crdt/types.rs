@@ -1,10 +1,50 @@
+#[cfg(feature = "maps")]
 mod awormap;
+#[cfg(feature = "sets")]
 mod aworset;
+#[cfg(feature = "counters")]
+mod bcounter;
+#[cfg(feature = "sets")]
+mod digest;
+#[cfg(feature = "counters")]
 mod gcounter;
+#[cfg(feature = "sets")]
+mod gset;
+#[cfg(feature = "maps")]
+mod leaderboard;
+#[cfg(feature = "sets")]
+mod orbag;
+#[cfg(feature = "counters")]
 mod pncounter;
+#[cfg(feature = "counters")]
+mod rcounter;
+#[cfg(feature = "registers")]
+mod register;
+#[cfg(feature = "sets")]
+mod twopset;
 
 pub use super::*;
-pub use awormap::AWORMap;
-pub use aworset::AWORSet;
-pub use gcounter::GCounter;
+#[cfg(feature = "maps")]
+pub use awormap::{AWORMap, AWORMapBase, AWORMapDelta, AWORMapEntry, OrdAWORMap, ValueStore};
+#[cfg(feature = "sets")]
+pub use aworset::{AWORSet, DotKernel};
+#[cfg(feature = "counters")]
+pub use bcounter::{BoundedCounter, InsufficientRights};
+#[cfg(feature = "sets")]
+pub use digest::{SetDiff, SetDigest, MAX_DIGEST_DEPTH};
+#[cfg(feature = "counters")]
+pub use gcounter::{CounterValue, GCounter, Overflow};
+#[cfg(feature = "sets")]
+pub use gset::GSet;
+#[cfg(feature = "maps")]
+pub use leaderboard::Leaderboard;
+#[cfg(feature = "sets")]
+pub use orbag::ORBag;
+#[cfg(feature = "counters")]
 pub use pncounter::PNCounter;
+#[cfg(feature = "counters")]
+pub use rcounter::ResettableCounter;
+#[cfg(feature = "registers")]
+pub use register::{MaxRegister, MinRegister};
+#[cfg(feature = "sets")]
+pub use twopset::{TwoPhaseSet, TwoPhaseSetDelta};
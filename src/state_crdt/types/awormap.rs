@@ -1,19 +1,227 @@
 use super::aworset::DotKernel;
-use super::{AWORSet, Convergent, ReplicaId};
-use std::borrow::Borrow;
-use std::collections::HashMap;
-use std::hash::Hash;
+use super::causal::{Dot, DotContext};
+use super::{AWORSet, Convergent, DeltaSince, FromReplica, ReplicaId};
+use crate::collections::{BTreeMap, HashMap, HashSet};
+use crate::vclock::VClock;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::convert::Infallible;
+use core::hash::Hash;
+use core::marker::PhantomData;
+use core::ops::{Bound, Deref, RangeBounds};
 
+/// Storage for the values of an add-wins map. Keys are looked up in the
+/// map's key set first, so stores are only ever queried by their own keys.
+/// The key set is the map's index of keys, stores don't need to keep them.
+pub trait ValueStore<K, V>: Sized {
+    /// Error raised when the storage of the values fails
+    type Error;
+
+    /// Reference to a stored value, stores which don't keep every value
+    /// in memory may hand out a copy instead
+    type Ref<'a>: Deref<Target = V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn contains_key(&self, key: &K) -> bool;
+
+    /// Iterates over the keys with values. Stores which don't keep their
+    /// keys pick them from `map_keys`, the keys of the map's key set.
+    fn keys<'a>(
+        &'a self,
+        map_keys: impl Iterator<Item = &'a K> + 'a,
+    ) -> impl Iterator<Item = &'a K>
+    where
+        K: 'a;
+
+    fn get(&self, key: &K) -> Result<Option<Self::Ref<'_>>, Self::Error>;
+
+    fn get_mut(&mut self, key: &K) -> Result<Option<&mut V>, Self::Error>;
+
+    fn insert(&mut self, key: K, value: V) -> Result<Option<V>, Self::Error>;
+
+    fn remove(&mut self, key: &K) -> Result<Option<V>, Self::Error>;
+
+    /// Iterates over the entries, with keys taken from `map_keys`
+    /// by stores which don't keep them
+    fn iter<'a>(
+        &'a self,
+        map_keys: impl Iterator<Item = &'a K> + 'a,
+    ) -> impl Iterator<Item = Result<(&'a K, Self::Ref<'a>), Self::Error>>
+    where
+        K: 'a,
+        V: 'a;
+
+    fn into_entries(self) -> impl Iterator<Item = Result<(K, V), Self::Error>>;
+}
+
+impl<K: Eq + Hash, V> ValueStore<K, V> for HashMap<K, V> {
+    type Error = Infallible;
+    type Ref<'a>
+        = &'a V
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        HashMap::contains_key(self, key)
+    }
+
+    fn keys<'a>(&'a self, _: impl Iterator<Item = &'a K> + 'a) -> impl Iterator<Item = &'a K>
+    where
+        K: 'a,
+    {
+        HashMap::keys(self)
+    }
+
+    fn get(&self, key: &K) -> Result<Option<&V>, Infallible> {
+        Ok(HashMap::get(self, key))
+    }
+
+    fn get_mut(&mut self, key: &K) -> Result<Option<&mut V>, Infallible> {
+        Ok(HashMap::get_mut(self, key))
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Result<Option<V>, Infallible> {
+        Ok(HashMap::insert(self, key, value))
+    }
+
+    fn remove(&mut self, key: &K) -> Result<Option<V>, Infallible> {
+        Ok(HashMap::remove(self, key))
+    }
+
+    fn iter<'a>(
+        &'a self,
+        _: impl Iterator<Item = &'a K> + 'a,
+    ) -> impl Iterator<Item = Result<(&'a K, &'a V), Infallible>>
+    where
+        K: 'a,
+        V: 'a,
+    {
+        HashMap::iter(self).map(Ok)
+    }
+
+    fn into_entries(self) -> impl Iterator<Item = Result<(K, V), Infallible>> {
+        self.into_iter().map(Ok)
+    }
+}
+
+impl<K: Ord, V> ValueStore<K, V> for BTreeMap<K, V> {
+    type Error = Infallible;
+    type Ref<'a>
+        = &'a V
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        BTreeMap::contains_key(self, key)
+    }
+
+    fn keys<'a>(&'a self, _: impl Iterator<Item = &'a K> + 'a) -> impl Iterator<Item = &'a K>
+    where
+        K: 'a,
+    {
+        BTreeMap::keys(self)
+    }
+
+    fn get(&self, key: &K) -> Result<Option<&V>, Infallible> {
+        Ok(BTreeMap::get(self, key))
+    }
+
+    fn get_mut(&mut self, key: &K) -> Result<Option<&mut V>, Infallible> {
+        Ok(BTreeMap::get_mut(self, key))
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Result<Option<V>, Infallible> {
+        Ok(BTreeMap::insert(self, key, value))
+    }
+
+    fn remove(&mut self, key: &K) -> Result<Option<V>, Infallible> {
+        Ok(BTreeMap::remove(self, key))
+    }
+
+    fn iter<'a>(
+        &'a self,
+        _: impl Iterator<Item = &'a K> + 'a,
+    ) -> impl Iterator<Item = Result<(&'a K, &'a V), Infallible>>
+    where
+        K: 'a,
+        V: 'a,
+    {
+        BTreeMap::iter(self).map(Ok)
+    }
+
+    fn into_entries(self) -> impl Iterator<Item = Result<(K, V), Infallible>> {
+        self.into_iter().map(Ok)
+    }
+}
+
+/// Add-wins map parametrized by the storage of its values. Maps over
+/// stores which can fail are used through the `try_` methods, the rest
+/// of the API is available for in-memory stores only.
 #[derive(Debug, Clone)]
-pub struct AWORMap<K, V> {
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "K: serde::Serialize + Eq + Hash + Clone, V: serde::Serialize, \
+                     S: serde::Serialize",
+        deserialize = "K: serde::Deserialize<'de> + Eq + Hash + Clone, \
+                       V: serde::Deserialize<'de>, S: serde::Deserialize<'de>"
+    ))
+)]
+pub struct AWORMapBase<K, V, S> {
     keys: AWORSet<K>,
-    vals: HashMap<K, V>,
+    vals: S,
+    // removed keys with their dots and the removed values,
+    // which tell what the removal delta of the value is
+    removed: Vec<(K, DotContext, Option<V>)>,
+    // keys whose values may have deltas to take
+    dirty: HashSet<K>,
+    _marker: PhantomData<V>,
 }
 
+/// Add-wins map with values stored in a `HashMap`
+pub type AWORMap<K, V> = AWORMapBase<K, V, HashMap<K, V>>;
+
+/// Add-wins map with values stored in a `BTreeMap`, which gives
+/// deterministic iteration order and range queries over the keys.
+/// Keys still need to be hashable as the key set is hash based.
+pub type OrdAWORMap<K, V> = AWORMapBase<K, V, BTreeMap<K, V>>;
+
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "K: serde::Serialize + Eq + Hash, V: serde::Serialize",
+        deserialize = "K: serde::Deserialize<'de> + Eq + Hash, V: serde::Deserialize<'de>"
+    ))
+)]
 pub struct AWORMapDelta<K, V> {
     keys: Option<DotKernel<K>>,
     vals: HashMap<K, V>,
+    // dots of the removed keys along with the removal deltas of their
+    // values, receivers apply them to their values so that concurrent
+    // updates survive. Values without removal deltas are reset unless
+    // the receiver has seen updates of the key the remover has not.
+    removed: Vec<(K, DotContext, Option<V>)>,
 }
 
 impl<K, V> Default for AWORMapDelta<K, V> {
@@ -21,37 +229,252 @@ impl<K, V> Default for AWORMapDelta<K, V> {
         Self {
             keys: None,
             vals: HashMap::new(),
+            removed: Vec::new(),
         }
     }
 }
 
-impl<K: Eq + Hash + Clone, V> AWORMap<K, V> {
-    pub fn new(replica_id: ReplicaId) -> Self {
+impl<K, V> AWORMapDelta<K, V> {
+    /// Returns the dots of the keys the delta adds along with
+    /// the context of the key set, if the key set has changed
+    pub fn added(&self) -> Option<&DotKernel<K>> {
+        self.keys.as_ref()
+    }
+
+    /// Returns the deltas of the values
+    pub fn values(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.vals.iter()
+    }
+
+    /// Returns the removed keys along with the dots they were removed at
+    pub fn removed(&self) -> impl Iterator<Item = (&K, &DotContext)> {
+        self.removed.iter().map(|(k, dots, _)| (k, dots))
+    }
+}
+
+impl<K: Eq + Hash + Clone, V, S: ValueStore<K, V>> AWORMapBase<K, V, S> {
+    pub fn new(replica_id: ReplicaId) -> Self
+    where
+        S: Default,
+    {
+        Self::with_store(replica_id, S::default())
+    }
+
+    /// Creates the map on top of the given value storage
+    pub fn with_store(replica_id: ReplicaId, vals: S) -> Self {
         Self {
             keys: AWORSet::new(replica_id),
-            vals: HashMap::new(),
+            vals,
+            removed: Vec::new(),
+            dirty: HashSet::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vals.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.vals.keys(self.keys.keys())
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.keys
+            .get(key)
+            .is_some_and(|k| self.vals.contains_key(k))
+    }
+
+    pub fn try_get<Q>(&self, key: &Q) -> Result<Option<S::Ref<'_>>, S::Error>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        match self.keys.get(key) {
+            Some(k) => self.vals.get(k),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a mutable reference to the value without touching the key,
+    /// use `try_update` for changes which should survive concurrent removals
+    pub fn try_get_mut<Q>(&mut self, key: &Q) -> Result<Option<&mut V>, S::Error>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let Some(k) = self.keys.get(key) else {
+            return Ok(None);
+        };
+        let v = self.vals.get_mut(k)?;
+        if v.is_some() && !self.dirty.contains::<K>(k) {
+            self.dirty.insert(k.clone());
         }
+        Ok(v)
+    }
+
+    pub fn try_iter(&self) -> impl Iterator<Item = Result<(&K, S::Ref<'_>), S::Error>> {
+        self.vals.iter(self.keys.keys())
+    }
+
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<(), S::Error> {
+        self.keys.add(key.clone());
+        self.dirty.insert(key.clone());
+        self.vals.insert(key, value)?;
+        Ok(())
     }
 
-    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    /// Applies the function to the value stored under the key, creating it
+    /// if missing. Unlike mutating through `try_get_mut`, the key is added
+    /// again, so the update wins over concurrent removals of the same key.
+    pub fn try_update<F: FnOnce(&mut V)>(&mut self, key: K, f: F) -> Result<(), S::Error>
+    where
+        V: FromReplica,
+    {
+        self.keys.add(key.clone());
+        self.dirty.insert(key.clone());
+        match self.vals.get_mut(&key)? {
+            Some(v) => f(v),
+            None => {
+                let mut v = self.new_value();
+                f(&mut v);
+                self.vals.insert(key, v)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn try_remove<Q>(&mut self, key: &Q) -> Result<(), S::Error>
     where
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
-        self.vals.get(key)
+        let Some(key) = self.keys.get(key).cloned() else {
+            return Ok(());
+        };
+        let value = self.vals.remove(&key)?;
+        self.dirty.remove::<K>(&key);
+        match value {
+            Some(v) => self.remove_key(key, Some(v)),
+            None => self.keys.remove::<K>(&key),
+        }
+        Ok(())
     }
 
+    /// Returns the dots under which the key was added
+    pub fn dots<Q>(&self, key: &Q) -> impl Iterator<Item = Dot> + '_
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.keys.dots(key)
+    }
+
+    /// Returns the dots the key set has seen, including removed ones
+    pub fn context(&self) -> &DotContext {
+        self.keys.context()
+    }
+
+    /// Returns the version vector of the key set, to be passed
+    /// to `delta_since` on the replicas to catch up from
+    pub fn clock(&self) -> VClock {
+        self.keys.clock()
+    }
+
+    /// Creates a value on behalf of the map's replica
+    fn new_value(&self) -> V
+    where
+        V: FromReplica,
+    {
+        V::from_replica(self.keys.replica_id())
+    }
+
+    fn drop_removed(&mut self, changed: &[K]) -> Result<(), S::Error> {
+        for key in changed {
+            if !self.keys.contains(key) {
+                self.vals.remove(key)?;
+                self.dirty.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_key(&mut self, key: K, value: Option<V>) {
+        let mut dots = DotContext::new();
+        for dot in self.keys.dots(&key) {
+            dots.add(dot);
+        }
+        self.keys.remove(&key);
+        self.removed.push((key, dots, value));
+    }
+}
+
+impl<K, V, S> AWORMapBase<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: ValueStore<K, V, Error = Infallible>,
+{
+    pub fn get<Q>(&self, key: &Q) -> Option<S::Ref<'_>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let Ok(v) = self.try_get(key);
+        v
+    }
+
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, S::Ref<'_>)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let k = self.keys.get(key)?;
+        let Ok(v) = self.vals.get(k);
+        v.map(|v| (k, v))
+    }
+
+    /// Returns a mutable reference to the value without touching the key,
+    /// use `update` for changes which should survive concurrent removals
     pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
     where
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
-        self.vals.get_mut(key)
+        let Ok(v) = self.try_get_mut(key);
+        v
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, S::Ref<'_>)> {
+        self.try_iter().map(|e| {
+            let Ok(e) = e;
+            e
+        })
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = S::Ref<'_>> {
+        self.iter().map(|(_, v)| v)
     }
 
     pub fn insert(&mut self, key: K, value: V) {
-        self.keys.add(key.clone());
-        self.vals.insert(key, value);
+        let Ok(()) = self.try_insert(key, value);
+    }
+
+    /// Applies the function to the value stored under the key, creating it
+    /// if missing. Unlike mutating through `get_mut`, the key is added again,
+    /// so the update wins over concurrent removals of the same key.
+    pub fn update<F: FnOnce(&mut V)>(&mut self, key: K, f: F)
+    where
+        V: FromReplica,
+    {
+        let Ok(()) = self.try_update(key, f);
     }
 
     pub fn remove<Q>(&mut self, key: &Q)
@@ -59,92 +482,300 @@ impl<K: Eq + Hash + Clone, V> AWORMap<K, V> {
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
-        self.keys.remove(key);
-        self.vals.remove(key);
+        let Ok(()) = self.try_remove(key);
+    }
+
+    pub fn entry(&mut self, key: K) -> AWORMapEntry<'_, K, V, S> {
+        AWORMapEntry { map: self, key }
     }
 }
 
-impl<K: Eq + Hash + Clone, V: Convergent + Default> Convergent for AWORMap<K, V> {
-    type Delta = AWORMapDelta<K, V::Delta>;
+/// View into a single entry of an add-wins map, a vacant entry is added
+/// to the key set only when a value is inserted into it
+pub struct AWORMapEntry<'a, K, V, S> {
+    map: &'a mut AWORMapBase<K, V, S>,
+    key: K,
+}
 
-    fn merge(&mut self, other: Self) {
-        let mut other = other;
-        self.keys.merge(other.keys);
-
-        let mut self_vals = std::mem::take(&mut self.vals);
-
-        for key in self.keys.keys() {
-            let v1 = self_vals.remove(key);
-            let v2 = other.vals.remove(key);
-            match (v1, v2) {
-                (Some(mut v1), Some(v2)) => {
-                    v1.merge(v2);
-                    self.vals.insert(key.clone(), v1);
-                }
-                (Some(v1), None) => {
-                    self.vals.insert(key.clone(), v1);
-                }
-                (None, Some(v2)) => {
-                    self.vals.insert(key.clone(), v2);
-                }
-                _ => {}
+impl<'a, K, V, S> AWORMapEntry<'a, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: ValueStore<K, V, Error = Infallible>,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        if let Some(v) = self.map.get_mut(&self.key) {
+            f(v);
+        }
+        self
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+        if !self.map.contains_key(&self.key) {
+            self.map.insert(self.key.clone(), f());
+        }
+        self.map.get_mut(&self.key).unwrap()
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<K: Ord + Hash + Clone, V> OrdAWORMap<K, V> {
+    pub fn range<Q, R>(&self, range: R) -> impl DoubleEndedIterator<Item = (&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        self.vals.range(range)
+    }
+
+    pub fn first(&self) -> Option<(&K, &V)> {
+        self.vals.first_key_value()
+    }
+
+    pub fn last(&self) -> Option<(&K, &V)> {
+        self.vals.last_key_value()
+    }
+}
+
+impl<K: Ord + Hash + Clone + Borrow<str>, V> OrdAWORMap<K, V> {
+    /// Iterates over entries whose keys start with the prefix
+    pub fn prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a K, &'a V)> {
+        self.vals
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(k, _)| (*k).borrow().starts_with(prefix))
+    }
+}
+
+impl<K, V, S> AWORMapBase<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: DeltaSince + FromReplica,
+    S: ValueStore<K, V>,
+{
+    pub fn try_merge(&mut self, other: Self) -> Result<(), S::Error> {
+        self.try_merge_tracked(other)?;
+        Ok(())
+    }
+
+    pub fn try_merge_delta(&mut self, delta: AWORMapDelta<K, V::Delta>) -> Result<(), S::Error> {
+        self.try_merge_delta_tracked(delta)?;
+        Ok(())
+    }
+
+    pub fn try_take_delta(&mut self) -> Result<Option<AWORMapDelta<K, V::Delta>>, S::Error> {
+        let keys = self.keys.take_delta();
+        let mut vals = HashMap::new();
+
+        for key in core::mem::take(&mut self.dirty) {
+            if let Some(d) = self.vals.get_mut(&key)?.and_then(|v| v.take_delta()) {
+                vals.insert(key, d);
+            }
+        }
+
+        // a key added again without a change of its value may have been
+        // removed concurrently along with the value, so ship all of it
+        for key in keys.iter().flat_map(|k| k.store.0.values()) {
+            if vals.contains_key(key) {
+                continue;
+            }
+            if let Some(d) = self
+                .vals
+                .get(key)?
+                .and_then(|v| v.delta_since(&VClock::new()))
+            {
+                vals.insert(key.clone(), d);
             }
         }
+
+        let removed: Vec<_> = core::mem::take(&mut self.removed)
+            .into_iter()
+            .map(|(k, dots, v)| (k, dots, v.and_then(|v| v.removal_delta())))
+            .collect();
+
+        if keys.is_none() && vals.is_empty() && removed.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(AWORMapDelta {
+                keys,
+                vals,
+                removed,
+            }))
+        }
     }
 
-    fn merge_delta(&mut self, delta: Self::Delta) {
-        let mut delta = delta;
+    /// Merges the other map and returns the keys which were added,
+    /// removed or whose values were merged
+    fn try_merge_tracked(&mut self, other: Self) -> Result<Vec<K>, S::Error> {
+        let mut changed = self.keys.merge_tracked(other.keys);
+        self.drop_removed(&changed)?;
 
-        if let Some(delta_keys) = delta.keys {
-            self.keys.merge_delta(delta_keys);
+        for entry in other.vals.into_entries() {
+            let (key, v2) = entry?;
+            if !self.keys.contains(&key) {
+                continue;
+            }
+            match self.vals.get_mut(&key)? {
+                Some(v1) => v1.merge(v2),
+                None => {
+                    // the value keeps the replica id of this map
+                    let mut v = self.new_value();
+                    v.merge(v2);
+                    self.vals.insert(key.clone(), v)?;
+                }
+            }
+            changed.push(key);
         }
 
-        let mut self_vals = std::mem::take(&mut self.vals);
+        // merged values may forward what they received in their deltas
+        self.dirty.extend(changed.iter().cloned());
+        Ok(changed)
+    }
 
-        for key in self.keys.keys() {
-            let v = self_vals.remove(key);
-            let d = delta.vals.remove(key);
-            match (v, d) {
-                (Some(mut v), Some(d)) => {
+    /// Merges the delta and returns the keys which were added,
+    /// removed or whose values were merged
+    fn try_merge_delta_tracked(
+        &mut self,
+        delta: AWORMapDelta<K, V::Delta>,
+    ) -> Result<Vec<K>, S::Error> {
+        let mut changed = Vec::new();
+        for (key, dots, removal) in delta.removed {
+            match (self.vals.get_mut(&key)?, removal) {
+                (Some(v), Some(d)) => {
+                    // the value forwards the removal in its own delta
                     v.merge_delta(d);
-                    self.vals.insert(key.clone(), v);
-                }
-                (Some(v), None) => {
-                    self.vals.insert(key.clone(), v);
+                    changed.push(key);
                 }
                 (None, Some(d)) => {
-                    // this is not quite right as some types
-                    // needs ReplicaId in order to be constructed
-                    let mut v = V::default();
+                    let mut v = self.new_value();
                     v.merge_delta(d);
-                    self.vals.insert(key.clone(), v);
+                    self.removed.push((key, dots, Some(v)));
+                }
+                (_, None) => {
+                    // reset the value unless there are updates the remover has not seen
+                    if self.keys.dots(&key).all(|dot| dots.contains(&dot)) {
+                        self.vals.remove(&key)?;
+                        changed.push(key.clone());
+                    }
+                    self.removed.push((key, dots, None));
                 }
-                _ => {}
             }
         }
+
+        if let Some(delta_keys) = delta.keys {
+            let keys = self.keys.merge_delta_tracked(delta_keys);
+            self.drop_removed(&keys)?;
+            changed.extend(keys);
+        }
+
+        for (key, d) in delta.vals {
+            if !self.keys.contains(&key) {
+                continue;
+            }
+            match self.vals.get_mut(&key)? {
+                Some(v) => v.merge_delta(d),
+                None => {
+                    let mut v = self.new_value();
+                    v.merge_delta(d);
+                    self.vals.insert(key.clone(), v)?;
+                }
+            }
+            changed.push(key);
+        }
+
+        // merged values may forward what they received in their deltas
+        self.dirty.extend(changed.iter().cloned());
+        Ok(changed)
+    }
+}
+
+impl<K, V, S> AWORMapBase<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: DeltaSince + FromReplica,
+    S: ValueStore<K, V, Error = Infallible>,
+{
+    /// Merges the other map and returns the keys which were added,
+    /// removed or whose values were merged
+    pub(super) fn merge_tracked(&mut self, other: Self) -> Vec<K> {
+        let Ok(changed) = self.try_merge_tracked(other);
+        changed
+    }
+
+    /// Merges the delta and returns the keys which were added,
+    /// removed or whose values were merged
+    pub(super) fn merge_delta_tracked(&mut self, delta: AWORMapDelta<K, V::Delta>) -> Vec<K> {
+        let Ok(changed) = self.try_merge_delta_tracked(delta);
+        changed
+    }
+}
+
+impl<K, V, S> Convergent for AWORMapBase<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: DeltaSince + FromReplica,
+    S: ValueStore<K, V, Error = Infallible>,
+{
+    type Delta = AWORMapDelta<K, V::Delta>;
+
+    fn merge(&mut self, other: Self) {
+        self.merge_tracked(other);
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) {
+        self.merge_delta_tracked(delta);
     }
 
     fn take_delta(&mut self) -> Option<Self::Delta> {
-        let keys = self.keys.take_delta();
+        let Ok(delta) = self.try_take_delta();
+        delta
+    }
+}
+
+impl<K, V, S> DeltaSince for AWORMapBase<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: DeltaSince + FromReplica,
+    S: ValueStore<K, V, Error = Infallible>,
+{
+    /// Returns the keys added under dots not covered by the clock with their
+    /// whole values, and the keys removed since. Versions only advance when
+    /// a key is added, so changes made through `get_mut` or `and_modify` are not
+    /// picked up, use `update` for values which have to be caught up.
+    fn delta_since(&self, clock: &VClock) -> Option<Self::Delta> {
+        let keys = self.keys.delta_since(clock)?;
         let mut vals = HashMap::new();
 
-        for (k, v) in &mut self.vals {
-            if let Some(d) = v.take_delta() {
+        for k in keys.store.0.values() {
+            if let Some(d) = self.get(k).and_then(|v| v.delta_since(&VClock::new())) {
                 vals.insert(k.clone(), d);
             }
         }
 
-        if keys.is_none() && vals.is_empty() {
-            None
-        } else {
-            Some(AWORMapDelta { keys, vals })
-        }
+        Some(AWORMapDelta {
+            keys: Some(keys),
+            vals,
+            removed: Vec::new(),
+        })
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "counters"))]
 mod tests {
-    use super::super::GCounter;
+    use super::super::{GCounter, MaxRegister};
     use super::*;
 
     const REPLICA_1: ReplicaId = 123;
@@ -153,13 +784,13 @@ mod tests {
     #[test]
     fn basic_sync() {
         let mut m1 = AWORMap::new(REPLICA_1);
-        m1.insert("foo".to_owned(), GCounter::new());
-        m1.get_mut("foo").unwrap().inc(REPLICA_1);
+        m1.insert("foo".to_owned(), GCounter::<usize>::new());
+        m1.get_mut("foo").unwrap().inc(REPLICA_1).unwrap();
         assert_eq!(m1.get("foo").unwrap().value(), 1);
 
         let mut m2 = AWORMap::new(REPLICA_2);
         m2.insert("foo".to_owned(), GCounter::new());
-        m2.get_mut("foo").unwrap().inc(REPLICA_2);
+        m2.get_mut("foo").unwrap().inc(REPLICA_2).unwrap();
         assert_eq!(m2.get("foo").unwrap().value(), 1);
 
         m2.merge(m1.clone());
@@ -169,7 +800,7 @@ mod tests {
     #[test]
     fn can_remove_value() {
         let mut m1 = AWORMap::new(REPLICA_1);
-        m1.insert("foo".to_owned(), GCounter::new());
+        m1.insert("foo".to_owned(), GCounter::<usize>::new());
         m1.remove("foo");
         assert!(m1.get("foo").is_none());
     }
@@ -177,12 +808,12 @@ mod tests {
     #[test]
     fn merge_after_removal() {
         let mut m1 = AWORMap::new(REPLICA_1);
-        m1.insert("foo".to_owned(), GCounter::new());
+        m1.insert("foo".to_owned(), GCounter::<usize>::new());
         m1.remove("foo");
 
         let mut m2 = AWORMap::new(REPLICA_2);
         m2.insert("foo".to_owned(), GCounter::new());
-        m2.get_mut("foo").unwrap().inc(REPLICA_2);
+        m2.get_mut("foo").unwrap().inc(REPLICA_2).unwrap();
 
         m2.merge(m1.clone());
         assert_eq!(m2.get("foo").unwrap().value(), 1);
@@ -191,17 +822,419 @@ mod tests {
     #[test]
     fn basic_delta_sync() {
         let mut m1 = AWORMap::new(REPLICA_1);
-        m1.insert("foo".to_owned(), GCounter::new());
-        m1.get_mut("foo").unwrap().inc(REPLICA_1);
+        m1.insert("foo".to_owned(), GCounter::<usize>::new());
+        m1.get_mut("foo").unwrap().inc(REPLICA_1).unwrap();
         assert_eq!(m1.get("foo").unwrap().value(), 1);
 
         let mut m2 = AWORMap::new(REPLICA_2);
         m2.insert("bar".to_owned(), GCounter::new());
-        m2.get_mut("bar").unwrap().inc(REPLICA_2);
+        m2.get_mut("bar").unwrap().inc(REPLICA_2).unwrap();
         assert_eq!(m2.get("bar").unwrap().value(), 1);
 
         m1.merge_delta(m2.take_delta().unwrap());
         assert_eq!(m1.get("foo").unwrap().value(), 1);
         assert_eq!(m1.get("bar").unwrap().value(), 1);
     }
+
+    #[test]
+    fn nested_register_delta_sync() {
+        let mut m1 = AWORMap::new(REPLICA_1);
+        m1.insert("foo".to_owned(), MaxRegister::new(REPLICA_1));
+        m1.get_mut("foo").unwrap().set(3);
+
+        let mut m2: AWORMap<String, MaxRegister<i32>> = AWORMap::new(REPLICA_2);
+        m2.merge_delta(m1.take_delta().unwrap());
+        assert_eq!(m2.get("foo").unwrap().value(), Some(&3));
+
+        m2.get_mut("foo").unwrap().set(5);
+        m1.merge_delta(m2.take_delta().unwrap());
+        assert_eq!(m1.get("foo").unwrap().value(), Some(&5));
+    }
+
+    #[test]
+    fn ordered_iteration() {
+        let mut m1 = OrdAWORMap::new(REPLICA_1);
+        let mut m2 = OrdAWORMap::new(REPLICA_2);
+
+        for key in ["delta", "alpha", "charlie"] {
+            m1.insert(key.to_owned(), GCounter::<usize>::new());
+        }
+        m2.insert("bravo".to_owned(), GCounter::new());
+        m2.get_mut("bravo").unwrap().inc(REPLICA_2).unwrap();
+
+        m1.merge(m2);
+
+        let keys: Vec<_> = m1.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec!["alpha", "bravo", "charlie", "delta"]);
+        assert_eq!(m1.get("bravo").unwrap().value(), 1);
+        assert_eq!(m1.first().unwrap().0, "alpha");
+        assert_eq!(m1.last().unwrap().0, "delta");
+    }
+
+    #[test]
+    fn ordered_range_and_prefix() {
+        let mut m1 = OrdAWORMap::new(REPLICA_1);
+        for key in ["user:1", "user:2", "group:1", "user:3", "users"] {
+            m1.insert(key.to_owned(), GCounter::<usize>::new());
+        }
+        m1.remove("user:2");
+
+        let keys: Vec<_> = m1.prefix("user:").map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec!["user:1", "user:3"]);
+
+        let keys: Vec<_> = m1
+            .range::<str, _>((Bound::Included("h"), Bound::Excluded("user:3")))
+            .map(|(k, _)| k.as_str())
+            .collect();
+        assert_eq!(keys, vec!["user:1"]);
+
+        let mut m2 = OrdAWORMap::new(REPLICA_2);
+        for n in 0..10u32 {
+            m2.insert(n, GCounter::<usize>::new());
+        }
+        let keys: Vec<_> = m2.range(3..=5).rev().map(|(k, _)| *k).collect();
+        assert_eq!(keys, vec![5, 4, 3]);
+    }
+
+    #[test]
+    fn ordered_delta_sync() {
+        let mut m1 = OrdAWORMap::new(REPLICA_1);
+        m1.insert("foo".to_owned(), GCounter::new());
+        m1.get_mut("foo").unwrap().inc(REPLICA_1).unwrap();
+
+        let mut m2: OrdAWORMap<String, GCounter> = OrdAWORMap::new(REPLICA_2);
+        m2.insert("bar".to_owned(), GCounter::new());
+        m2.merge_delta(m1.take_delta().unwrap());
+
+        assert_eq!(m2.get("foo").unwrap().value(), 1);
+        assert_eq!(m2.first().unwrap().0, "bar");
+    }
+
+    #[test]
+    fn iterators() {
+        let mut m1 = AWORMap::new(REPLICA_1);
+        assert!(m1.is_empty());
+
+        m1.insert("foo".to_owned(), GCounter::<usize>::new());
+        m1.insert("bar".to_owned(), GCounter::new());
+        m1.get_mut("bar").unwrap().inc(REPLICA_1).unwrap();
+
+        assert_eq!(m1.len(), 2);
+        assert!(m1.contains_key("foo"));
+        assert!(!m1.contains_key("baz"));
+
+        let mut keys: Vec<_> = m1.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["bar", "foo"]);
+
+        let total: usize = m1.values().map(|v| v.value()).sum();
+        assert_eq!(total, 1);
+        assert_eq!(m1.iter().count(), 2);
+    }
+
+    #[test]
+    fn entry_upsert() {
+        let mut m1 = AWORMap::new(REPLICA_1);
+
+        m1.entry("foo".to_owned())
+            .or_insert_with(GCounter::<usize>::new)
+            .inc(REPLICA_1)
+            .unwrap();
+        m1.entry("foo".to_owned())
+            .and_modify(|v| v.inc(REPLICA_1).unwrap())
+            .or_default()
+            .inc(REPLICA_1)
+            .unwrap();
+
+        assert_eq!(m1.get("foo").unwrap().value(), 3);
+        assert_eq!(m1.len(), 1);
+    }
+
+    #[test]
+    fn entry_registers_key() {
+        let mut m1: AWORMap<String, GCounter> = AWORMap::new(REPLICA_1);
+        m1.entry("foo".to_owned())
+            .or_default()
+            .inc(REPLICA_1)
+            .unwrap();
+
+        let mut m2: AWORMap<String, GCounter> = AWORMap::new(REPLICA_2);
+        m2.merge(m1.clone());
+        assert_eq!(m2.get("foo").unwrap().value(), 1);
+
+        let mut m3: AWORMap<String, GCounter> = AWORMap::new(REPLICA_2);
+        m3.merge_delta(m1.take_delta().unwrap());
+        assert_eq!(m3.get("foo").unwrap().value(), 1);
+
+        // entry removed on m1 is removed on replicas receiving the delta
+        m1.remove("foo");
+        m3.merge_delta(m1.take_delta().unwrap());
+        assert!(!m3.contains_key("foo"));
+    }
+
+    #[test]
+    fn ordered_entry() {
+        let mut m1 = OrdAWORMap::new(REPLICA_1);
+        for key in ["b", "a", "b", "c", "a", "b"] {
+            m1.entry(key)
+                .or_insert_with(GCounter::<usize>::new)
+                .inc(REPLICA_1)
+                .unwrap();
+        }
+
+        let counts: Vec<_> = m1.iter().map(|(k, v)| (*k, v.value())).collect();
+        assert_eq!(counts, vec![("a", 2), ("b", 3), ("c", 1)]);
+    }
+
+    #[test]
+    fn update_wins_over_concurrent_remove() {
+        let mut m1 = AWORMap::new(REPLICA_1);
+        m1.insert("foo".to_owned(), GCounter::<usize>::new());
+
+        let mut m2 = AWORMap::new(REPLICA_2);
+        m2.merge(m1.clone());
+
+        m1.update("foo".to_owned(), |v| v.inc(REPLICA_1).unwrap());
+        m2.remove("foo");
+
+        m1.merge(m2.clone());
+        m2.merge(m1.clone());
+
+        assert_eq!(m1.get("foo").unwrap().value(), 1);
+        assert_eq!(m2.get("foo").unwrap().value(), 1);
+    }
+
+    #[test]
+    fn get_mut_loses_to_concurrent_remove() {
+        let mut m1 = AWORMap::new(REPLICA_1);
+        m1.insert("foo".to_owned(), GCounter::<usize>::new());
+
+        let mut m2 = AWORMap::new(REPLICA_2);
+        m2.merge(m1.clone());
+
+        m1.get_mut("foo").unwrap().inc(REPLICA_1).unwrap();
+        m2.remove("foo");
+
+        m1.merge(m2);
+        assert!(m1.get("foo").is_none());
+    }
+
+    #[test]
+    fn update_delta_sync() {
+        let mut m1: AWORMap<String, GCounter> = AWORMap::new(REPLICA_1);
+        let mut m2: AWORMap<String, GCounter> = AWORMap::new(REPLICA_2);
+
+        m1.update("foo".to_owned(), |v| v.inc(REPLICA_1).unwrap());
+        m2.merge_delta(m1.take_delta().unwrap());
+        assert_eq!(m2.get("foo").unwrap().value(), 1);
+
+        // update and concurrent remove exchanged as deltas
+        m1.update("foo".to_owned(), |v| v.inc(REPLICA_1).unwrap());
+        m2.remove("foo");
+
+        let d1 = m1.take_delta().unwrap();
+        let d2 = m2.take_delta().unwrap();
+        m1.merge_delta(d2);
+        m2.merge_delta(d1);
+
+        assert_eq!(m1.get("foo").unwrap().value(), 2);
+        assert_eq!(m2.get("foo").unwrap().value(), 2);
+    }
+
+    #[test]
+    fn remove_delta_drops_value() {
+        let mut m1: AWORMap<String, GCounter> = AWORMap::new(REPLICA_1);
+        let mut m2: AWORMap<String, GCounter> = AWORMap::new(REPLICA_2);
+
+        m1.update("foo".to_owned(), |v| v.add(REPLICA_1, 5).unwrap());
+        m2.merge_delta(m1.take_delta().unwrap());
+
+        m1.remove("foo");
+        let delta = m1.take_delta().unwrap();
+        assert_eq!(delta.removed.len(), 1);
+
+        m2.merge_delta(delta);
+        assert!(m2.get("foo").is_none());
+    }
+
+    #[test]
+    fn remove_and_reinsert_resets_value() {
+        let mut m1: AWORMap<String, GCounter> = AWORMap::new(REPLICA_1);
+        let mut m2: AWORMap<String, GCounter> = AWORMap::new(REPLICA_2);
+
+        m1.update("foo".to_owned(), |v| v.add(REPLICA_1, 5).unwrap());
+        m2.merge_delta(m1.take_delta().unwrap());
+
+        // removal and re-insertion travel in the same delta
+        m1.remove("foo");
+        m1.update("foo".to_owned(), |v| v.add(REPLICA_1, 2).unwrap());
+        m2.merge_delta(m1.take_delta().unwrap());
+
+        assert_eq!(m1.get("foo").unwrap().value(), 2);
+        assert_eq!(m2.get("foo").unwrap().value(), 2);
+    }
+
+    #[test]
+    fn remove_delta_keeps_unseen_update() {
+        let mut m1: AWORMap<String, GCounter> = AWORMap::new(REPLICA_1);
+        let mut m2: AWORMap<String, GCounter> = AWORMap::new(REPLICA_2);
+
+        m1.update("foo".to_owned(), |v| v.add(REPLICA_1, 5).unwrap());
+        m2.merge_delta(m1.take_delta().unwrap());
+
+        // m2 removes the key while m1 keeps updating it
+        m2.remove("foo");
+        m1.update("foo".to_owned(), |v| v.inc(REPLICA_1).unwrap());
+
+        // m1 receives the removal after its own update
+        m1.merge_delta(m2.take_delta().unwrap());
+        assert_eq!(m1.get("foo").unwrap().value(), 6);
+
+        m2.merge_delta(m1.take_delta().unwrap());
+        assert_eq!(m2.get("foo").unwrap().value(), 6);
+    }
+
+    #[test]
+    fn remove_delta_resets_nested_set() {
+        let mut m1: AWORMap<String, AWORSet<&str>> = AWORMap::new(REPLICA_1);
+        let mut m2: AWORMap<String, AWORSet<&str>> = AWORMap::new(REPLICA_2);
+
+        m1.insert("foo".to_owned(), AWORSet::new(REPLICA_1));
+        m1.get_mut("foo").unwrap().add("a");
+        m2.merge_delta(m1.take_delta().unwrap());
+
+        // m2 removes the key while m1 concurrently adds another element
+        m2.remove("foo");
+        m1.update("foo".to_owned(), |s| s.add("b"));
+
+        let d1 = m1.take_delta().unwrap();
+        let d2 = m2.take_delta().unwrap();
+        m1.merge_delta(d2);
+        m2.merge_delta(d1);
+
+        // only the element m2 has seen is removed
+        for m in [&m1, &m2] {
+            let set = m.get("foo").unwrap();
+            assert_eq!(set.keys().collect::<Vec<_>>(), vec![&"b"]);
+        }
+    }
+
+    #[test]
+    fn nested_sets_use_map_replica() {
+        let mut m1: AWORMap<String, AWORSet<&str>> = AWORMap::new(REPLICA_1);
+        let mut m2: AWORMap<String, AWORSet<&str>> = AWORMap::new(REPLICA_2);
+
+        m1.update("k".to_owned(), |s| s.add("x"));
+        m2.update("k".to_owned(), |s| s.add("y"));
+        assert_eq!(m1.get("k").unwrap().replica_id(), REPLICA_1);
+
+        let mut m3 = m2.clone();
+        m3.merge(m1.clone());
+        let d1 = m1.take_delta().unwrap();
+        let d2 = m2.take_delta().unwrap();
+        m1.merge_delta(d2);
+        m2.merge_delta(d1);
+
+        for m in [&m1, &m2, &m3] {
+            let mut elems: Vec<_> = m.get("k").unwrap().keys().copied().collect();
+            elems.sort();
+            assert_eq!(elems, vec!["x", "y"]);
+        }
+
+        // a set merged from another replica adds under the local replica id
+        let mut m4: AWORMap<String, AWORSet<&str>> = AWORMap::new(789);
+        m4.merge(m1.clone());
+        m4.update("k".to_owned(), |s| s.add("z"));
+        m1.merge(m4);
+        assert_eq!(m1.get("k").unwrap().keys().count(), 3);
+    }
+
+    #[test]
+    fn update_without_change_survives_concurrent_remove() {
+        let mut m1: AWORMap<String, MaxRegister<i32>> = AWORMap::new(REPLICA_1);
+        let mut m2: AWORMap<String, MaxRegister<i32>> = AWORMap::new(REPLICA_2);
+
+        m1.update("a".to_owned(), |r| r.set(40));
+        m2.merge_delta(m1.take_delta().unwrap());
+
+        // m2 removes the key while m1 updates it without raising the value
+        m2.remove("a");
+        m1.update("a".to_owned(), |r| r.set(20));
+
+        let d1 = m1.take_delta().unwrap();
+        let d2 = m2.take_delta().unwrap();
+        m1.merge_delta(d2);
+        m2.merge_delta(d1);
+
+        assert_eq!(m1.get("a").unwrap().value(), Some(&40));
+        assert_eq!(m2.get("a").unwrap().value(), Some(&40));
+    }
+
+    #[test]
+    fn remove_key_without_value() {
+        let mut m1: AWORMap<String, MaxRegister<i32>> = AWORMap::new(REPLICA_1);
+        let mut m2: AWORMap<String, MaxRegister<i32>> = AWORMap::new(REPLICA_2);
+
+        // an empty register has no delta, so m2 only learns about the key
+        m1.insert("foo".to_owned(), MaxRegister::new(REPLICA_1));
+        m2.merge_delta(m1.take_delta().unwrap());
+        assert!(m2.get("foo").is_none());
+
+        m2.remove("foo");
+        m1.merge_delta(m2.take_delta().unwrap());
+        assert!(m1.get("foo").is_none());
+    }
+
+    #[test]
+    fn concurrent_removes_over_delta_sync() {
+        let mut m1: AWORMap<String, GCounter> = AWORMap::new(REPLICA_1);
+        let mut m2: AWORMap<String, GCounter> = AWORMap::new(REPLICA_2);
+
+        m1.update("foo".to_owned(), |v| v.inc(REPLICA_1).unwrap());
+        m1.update("bar".to_owned(), |v| v.inc(REPLICA_1).unwrap());
+        m2.merge_delta(m1.take_delta().unwrap());
+
+        m1.remove("foo");
+        m2.remove("foo");
+        m2.update("bar".to_owned(), |v| v.inc(REPLICA_2).unwrap());
+
+        let d1 = m1.take_delta().unwrap();
+        let d2 = m2.take_delta().unwrap();
+        m1.merge_delta(d2);
+        m2.merge_delta(d1);
+
+        for m in [&m1, &m2] {
+            assert!(m.get("foo").is_none());
+            assert_eq!(m.get("bar").unwrap().value(), 2);
+        }
+    }
+
+    #[test]
+    fn catch_up_from_clock() {
+        let mut m1 = AWORMap::new(REPLICA_1);
+        for key in 0..10 {
+            m1.update(key, |v: &mut GCounter| v.inc(REPLICA_1).unwrap());
+        }
+
+        let mut m2 = AWORMap::new(REPLICA_2);
+        m2.merge(m1.clone());
+        let clock = m2.clock();
+        assert!(m1.delta_since(&clock).is_none());
+
+        m1.update(3, |v| v.inc(REPLICA_1).unwrap());
+        m1.remove(&4);
+        m1.update(10, |v| v.add(REPLICA_1, 5).unwrap());
+
+        let delta = m1.delta_since(&clock).unwrap();
+        let mut keys: Vec<_> = delta.vals.keys().copied().collect();
+        keys.sort();
+        assert_eq!(keys, vec![3, 10]);
+
+        m2.merge_delta(delta);
+        assert_eq!(m2.get(&3).unwrap().value(), 2);
+        assert!(m2.get(&4).is_none());
+        assert_eq!(m2.get(&10).unwrap().value(), 5);
+        assert_eq!(m2.len(), m1.len());
+        assert!(m1.delta_since(&m2.clock()).is_none());
+    }
 }
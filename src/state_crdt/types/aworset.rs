@@ -1,226 +1,273 @@
-use super::{Convergent, ReplicaId};
-use std::borrow::Borrow;
-use std::collections::{BTreeSet, HashMap};
-
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
-struct Dot(ReplicaId, usize);
-
-type VectorClock = HashMap<ReplicaId, usize>;
-
-#[derive(Debug, Clone, Eq, PartialEq)]
-struct DotContext {
-    clock: VectorClock,
-    dots: BTreeSet<Dot>,
+use super::causal::{Causal, Dot, DotContext, DotFun};
+use super::{Convergent, DeltaSince, FromReplica, ReplicaId};
+use crate::collections::HashMap;
+use crate::vclock::VClock;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::hash::Hash;
+
+/// Dots of the set elements along with the set's causal context
+pub type DotKernel<K> = Causal<DotFun<K>>;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        from = "AWORSetState<K>",
+        into = "AWORSetState<K>",
+        bound(
+            serialize = "K: serde::Serialize + Eq + Hash + Clone",
+            deserialize = "K: serde::Deserialize<'de> + Eq + Hash + Clone"
+        )
+    )
+)]
+pub struct AWORSet<K> {
+    replica_id: ReplicaId,
+    state: DotKernel<K>,
+    // dots of every element, so lookups don't have to scan the kernel
+    index: HashMap<K, Vec<Dot>>,
+    delta: Option<DotKernel<K>>,
 }
 
-impl DotContext {
-    pub fn new() -> Self {
+impl<K: Eq + Hash + Clone> AWORSet<K> {
+    pub fn new(replica_id: ReplicaId) -> Self {
         Self {
-            clock: HashMap::new(),
-            dots: BTreeSet::new(),
+            replica_id,
+            state: DotKernel::new(),
+            index: HashMap::new(),
+            delta: None,
         }
     }
 
-    pub fn contains(&self, dot: &Dot) -> bool {
-        match self.clock.get(&dot.0) {
-            Some(n) if n >= &dot.1 => true,
-            _ => self.dots.contains(dot),
-        }
+    pub fn replica_id(&self) -> ReplicaId {
+        self.replica_id
     }
 
-    pub fn next_dot(&mut self, replica: ReplicaId) -> Dot {
-        let v = self
-            .clock
-            .entry(replica)
-            .and_modify(|n| *n += 1)
-            .or_insert(1);
-        Dot(replica, *v)
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.index.contains_key(value)
     }
 
-    pub fn add(&mut self, dot: Dot) {
-        self.dots.insert(dot);
+    /// Returns the stored element equal to the value
+    pub fn get<Q>(&self, value: &Q) -> Option<&K>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.index.get_key_value(value).map(|(k, _)| k)
     }
 
-    pub fn merge(&mut self, other: Self) {
-        for (k, v) in other.clock.into_iter() {
-            self.clock
-                .entry(k)
-                .and_modify(|x| *x = (*x).max(v))
-                .or_insert(v);
-        }
-        self.dots.extend(other.dots);
-        self.compact();
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.index.keys()
     }
 
-    fn compact(&mut self) {
-        let mut dots_to_remove = BTreeSet::new();
-
-        for dot in &self.dots {
-            let n = self.clock.get(&dot.0).map_or(0, |v| *v);
-            if dot.1 == n + 1 {
-                self.clock.insert(dot.0, dot.1 + 1);
-                dots_to_remove.insert(dot.clone());
-            } else if dot.1 <= n {
-                dots_to_remove.insert(dot.clone());
-            }
-        }
-
-        self.dots = &self.dots - &dots_to_remove;
+    /// Returns the dots under which the value was added
+    pub fn dots<Q>(&self, value: &Q) -> impl Iterator<Item = Dot> + '_
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.index.get(value).into_iter().flatten().copied()
     }
-}
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct DotKernel<K> {
-    context: DotContext,
-    entries: HashMap<Dot, K>,
-}
-
-impl<K> Default for DotKernel<K> {
-    fn default() -> Self {
-        Self {
-            context: DotContext::new(),
-            entries: HashMap::new(),
+    pub fn add(&mut self, value: K) {
+        if let Some(dots) = self.index.remove(&value) {
+            self.remove_dots(dots);
         }
-    }
-}
 
-impl<K: Clone> DotKernel<K> {
-    pub fn new() -> Self {
-        Default::default()
+        let delta = self.delta.get_or_insert_default();
+        let dot = self.state.context.next_dot(self.replica_id);
+        self.state.store.0.insert(dot, value.clone());
+        delta.store.0.insert(dot, value.clone());
+        delta.context.add(dot);
+        self.index.insert(value, vec![dot]);
     }
 
-    pub fn contains<Q>(&self, key: &Q) -> bool
+    pub fn remove<Q>(&mut self, value: &Q)
     where
         K: Borrow<Q>,
-        Q: Eq + ?Sized,
+        Q: Eq + Hash + ?Sized,
     {
-        self.entries.values().any(|k| k.borrow() == key)
+        if let Some(dots) = self.index.remove(value) {
+            self.remove_dots(dots);
+            // the removal gets a dot of its own, so that replicas
+            // catching up from a clock which saw the element learn of it
+            let dot = self.state.context.next_dot(self.replica_id);
+            self.delta.get_or_insert_default().context.add(dot);
+        }
     }
 
-    pub fn keys(&self) -> impl Iterator<Item = &K> {
-        self.entries.values()
+    /// Returns the dots the set has seen, including removed ones
+    pub fn context(&self) -> &DotContext {
+        &self.state.context
     }
 
-    pub fn add(&mut self, replica: ReplicaId, key: K, delta: &mut Self) {
-        let dot = self.context.next_dot(replica);
-        self.entries.insert(dot.clone(), key.clone());
-
-        delta.entries.insert(dot.clone(), key);
-        delta.context.add(dot);
-        delta.context.compact();
+    /// Returns the version vector of the set, to be passed
+    /// to `delta_since` on the replicas to catch up from
+    pub fn clock(&self) -> VClock {
+        self.state.context.clock()
     }
 
-    pub fn remove<Q>(&mut self, key: &Q, delta: &mut Self)
-    where
-        K: Borrow<Q>,
-        Q: Eq + ?Sized,
-    {
-        let mut dots_to_remove = Vec::new();
+    pub(super) fn kernel(&self) -> &DotKernel<K> {
+        &self.state
+    }
 
-        for (dot, k) in &self.entries {
-            if k.borrow() == key {
-                dots_to_remove.push(dot.clone());
-            }
+    /// Merges the other set and returns the elements whose dots have changed
+    pub(super) fn merge_tracked(&mut self, other: Self) -> Vec<K> {
+        if let Some(delta) = other.delta {
+            let d = self.delta.get_or_insert_default();
+            d.join(delta);
         }
 
-        for dot in dots_to_remove {
-            self.entries.remove(&dot);
-            delta.context.add(dot);
-        }
+        self.join_state(other.state)
+    }
 
-        delta.context.compact();
+    /// Merges the delta and returns the elements whose dots have changed
+    pub(super) fn merge_delta_tracked(&mut self, delta: DotKernel<K>) -> Vec<K> {
+        let self_delta = self.delta.get_or_insert_default();
+        self_delta.join(delta.clone());
+        self.join_state(delta)
     }
 
-    pub fn merge(&mut self, other: Self) {
-        let mut dots_to_remove = Vec::new();
+    fn remove_dots(&mut self, dots: Vec<Dot>) {
+        let delta = self.delta.get_or_insert_default();
+        for dot in dots {
+            self.state.store.0.remove(&dot);
+            delta.store.0.remove(&dot);
+            delta.context.add(dot);
+        }
+    }
 
-        for dot in self.entries.keys() {
-            // remove elements visible in dot context but not among entries
-            if other.context.contains(dot) && !other.entries.contains_key(dot) {
-                dots_to_remove.push(dot.clone());
+    fn join_state(&mut self, other: DotKernel<K>) -> Vec<K> {
+        let store = &self.state.store.0;
+
+        // look for removed dots on whichever side is smaller
+        let removed: Vec<Dot> = if other.context.len() < store.len() {
+            other
+                .context
+                .dots()
+                .filter(|dot| store.contains_key(dot) && !other.store.0.contains_key(dot))
+                .collect()
+        } else {
+            store
+                .keys()
+                .filter(|dot| other.context.contains(dot) && !other.store.0.contains_key(*dot))
+                .copied()
+                .collect()
+        };
+
+        let mut changed = Vec::new();
+
+        for dot in removed {
+            if let Some(k) = self.state.store.0.remove(&dot) {
+                if let Some(dots) = self.index.get_mut(&k) {
+                    dots.retain(|d| *d != dot);
+                    if dots.is_empty() {
+                        self.index.remove(&k);
+                    }
+                }
+                changed.push(k);
             }
         }
 
-        for (dot, k) in other.entries {
+        for (dot, k) in other.store.0 {
             // add unseen elements
-            if !self.entries.contains_key(&dot) && !self.context.contains(&dot) {
-                self.entries.insert(dot, k);
+            if !self.state.context.contains(&dot) && !self.state.store.0.contains_key(&dot) {
+                self.index.entry(k.clone()).or_default().push(dot);
+                self.state.store.0.insert(dot, k.clone());
+                changed.push(k);
             }
         }
 
-        for dot in &dots_to_remove {
-            self.entries.remove(dot);
-        }
-
-        self.context.merge(other.context);
+        self.state.context.merge(other.context);
+        changed
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct AWORSet<K> {
+/// Serialized form of the set, the element index is rebuilt on load
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AWORSetState<K> {
     replica_id: ReplicaId,
     state: DotKernel<K>,
     delta: Option<DotKernel<K>>,
 }
 
-impl<K: Eq + Clone> AWORSet<K> {
-    pub fn new(replica_id: ReplicaId) -> Self {
+#[cfg(feature = "serde")]
+impl<K> From<AWORSet<K>> for AWORSetState<K> {
+    fn from(set: AWORSet<K>) -> Self {
         Self {
-            replica_id,
-            state: DotKernel::new(),
-            delta: None,
+            replica_id: set.replica_id,
+            state: set.state,
+            delta: set.delta,
         }
     }
+}
 
-    pub fn contains<Q>(&self, value: &Q) -> bool
-    where
-        K: Borrow<Q>,
-        Q: Eq + ?Sized,
-    {
-        self.state.contains(value)
-    }
-
-    pub fn keys(&self) -> impl Iterator<Item = &K> {
-        self.state.keys()
-    }
+#[cfg(feature = "serde")]
+impl<K: Eq + Hash + Clone> From<AWORSetState<K>> for AWORSet<K> {
+    fn from(s: AWORSetState<K>) -> Self {
+        let mut index: HashMap<K, Vec<Dot>> = HashMap::new();
+        for (dot, k) in &s.state.store.0 {
+            index.entry(k.clone()).or_default().push(*dot);
+        }
 
-    pub fn add(&mut self, value: K) {
-        let delta = self.delta.get_or_insert_default();
-        self.state.remove(&value, delta);
-        self.state.add(self.replica_id, value, delta);
+        Self {
+            replica_id: s.replica_id,
+            state: s.state,
+            index,
+            delta: s.delta,
+        }
     }
+}
 
-    pub fn remove<Q>(&mut self, value: &Q)
-    where
-        K: Borrow<Q>,
-        Q: Eq + ?Sized,
-    {
-        let delta = self.delta.get_or_insert_default();
-        self.state.remove(value, delta);
+impl<K: PartialEq> PartialEq for AWORSet<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.replica_id == other.replica_id
+            && self.state == other.state
+            && self.delta == other.delta
     }
 }
 
-impl<K: Clone> Convergent for AWORSet<K> {
+impl<K: Eq> Eq for AWORSet<K> {}
+
+impl<K: Eq + Hash + Clone> Convergent for AWORSet<K> {
     type Delta = DotKernel<K>;
 
     fn merge(&mut self, other: Self) {
-        if let Some(delta) = other.delta {
-            let d = self.delta.get_or_insert(DotKernel::new());
-            d.merge(delta);
-        }
-
-        self.state.merge(other.state);
+        self.merge_tracked(other);
     }
 
     fn merge_delta(&mut self, delta: Self::Delta) {
-        let self_delta = self.delta.get_or_insert_default();
-        self_delta.merge(delta);
-        self.state.merge(self_delta.clone());
+        self.merge_delta_tracked(delta);
     }
 
     fn take_delta(&mut self) -> Option<Self::Delta> {
         self.delta.take()
     }
+
+    fn removal_delta(&self) -> Option<Self::Delta> {
+        Some(self.state.removal())
+    }
+}
+
+impl<K: Eq + Hash + Clone> FromReplica for AWORSet<K> {
+    fn from_replica(replica_id: ReplicaId) -> Self {
+        Self::new(replica_id)
+    }
+}
+
+impl<K: Eq + Hash + Clone> DeltaSince for AWORSet<K> {
+    /// Returns the elements added under dots not covered by the clock, along
+    /// with the dots removed since, so the receiver drops them as well
+    fn delta_since(&self, clock: &VClock) -> Option<Self::Delta> {
+        self.state.since(clock)
+    }
 }
 
 #[cfg(test)]
@@ -332,4 +379,96 @@ mod tests {
 
         assert_eq!(ab, abc);
     }
+
+    #[test]
+    fn remove_after_add_delta_sync() {
+        let mut s1 = AWORSet::new(REPLICA_1);
+        let mut s2 = AWORSet::new(REPLICA_2);
+
+        // both operations end up in the same delta
+        s1.add("foo");
+        s1.add("bar");
+        s1.remove("foo");
+        s2.merge_delta(s1.take_delta().unwrap());
+
+        assert!(!s2.contains("foo"));
+        assert!(s2.contains("bar"));
+    }
+
+    #[test]
+    fn merge_tracks_changed_elements() {
+        let mut s1 = AWORSet::new(REPLICA_1);
+        let mut s2 = AWORSet::new(REPLICA_2);
+
+        s1.add("foo");
+        s1.add("bar");
+        s2.merge_delta(s1.take_delta().unwrap());
+
+        s1.remove("foo");
+        s1.add("baz");
+        let mut changed = s2.merge_delta_tracked(s1.take_delta().unwrap());
+        changed.sort();
+        assert_eq!(changed, vec!["baz", "foo"]);
+
+        // merging the same state again changes nothing
+        assert!(s2.merge_tracked(s1.clone()).is_empty());
+        assert_eq!(s2.dots("bar").count(), 1);
+    }
+
+    #[test]
+    fn catch_up_from_clock() {
+        let mut s1 = AWORSet::new(REPLICA_1);
+        let mut s2 = AWORSet::new(REPLICA_2);
+
+        for i in 0..100 {
+            s1.add(i);
+        }
+        s2.merge(s1.clone());
+        s2.add(1000);
+
+        // s2 goes offline while s1 keeps changing
+        let clock = s2.clock();
+        s1.remove(&10);
+        s1.add(100);
+        s1.add(10);
+        s1.remove(&20);
+
+        let delta = s1.delta_since(&clock).unwrap();
+        let mut added: Vec<_> = delta.store.0.values().copied().collect();
+        added.sort();
+        assert_eq!(added, vec![10, 100]);
+
+        s2.merge_delta(delta);
+        assert!(s2.contains(&10) && s2.contains(&100) && s2.contains(&1000));
+        assert!(!s2.contains(&20));
+        assert_eq!(s2.keys().count(), 101);
+
+        // the other way around only ships the element s1 hasn't seen
+        let delta = s2.delta_since(&s1.clock()).unwrap();
+        assert_eq!(delta.store.0.values().collect::<Vec<_>>(), vec![&1000]);
+        s1.merge_delta(delta);
+        assert_eq!(s1.keys().count(), s2.keys().count());
+
+        assert!(AWORSet::<u32>::new(REPLICA_1).delta_since(&clock).is_none());
+    }
+
+    #[test]
+    fn delta_since_after_remove() {
+        let mut s1 = AWORSet::new(REPLICA_1);
+        let mut s2 = AWORSet::new(REPLICA_2);
+        s1.add(1);
+        s1.add(2);
+        s2.merge(s1.clone());
+
+        // a removal of an element the clock has seen is still shipped
+        s1.remove(&1);
+        let delta = s1.delta_since(&s2.clock()).unwrap();
+        assert!(delta.store.0.is_empty());
+        s2.merge_delta(delta);
+        assert!(!s2.contains(&1) && s2.contains(&2));
+
+        // nothing is left once the clock is up to date
+        assert!(s1.delta_since(&s2.clock()).is_none());
+        assert!(s2.delta_since(&s1.clock()).is_none());
+    }
 }
@@ -0,0 +1,274 @@
+use super::gcounter::clamp;
+use super::{Convergent, DeltaSince, PNCounter, ReplicaId};
+use crate::collections::HashMap;
+use crate::vclock::VClock;
+use core::fmt;
+
+/// Error returned when a replica tries to consume more rights than it holds
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct InsufficientRights {
+    pub available: usize,
+    pub requested: usize,
+}
+
+impl fmt::Display for InsufficientRights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "insufficient rights: requested {}, available {}",
+            self.requested, self.available
+        )
+    }
+}
+
+impl core::error::Error for InsufficientRights {}
+
+/// Counter which never goes below zero. Every increment grants a right
+/// to decrement to the replica which made it, rights can be transferred
+/// between replicas, and a replica can only decrement within the rights
+/// it holds locally.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoundedCounter {
+    counter: PNCounter,
+    transfers: HashMap<(ReplicaId, ReplicaId), usize>,
+}
+
+impl BoundedCounter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn value(&self) -> i128 {
+        self.counter.value()
+    }
+
+    /// Returns the number of decrements the replica is allowed to make
+    pub fn rights(&self, replica: ReplicaId) -> usize {
+        let mut rights = self.counter.get(&replica);
+
+        for ((from, to), n) in &self.transfers {
+            if *to == replica {
+                rights += *n as i128;
+            }
+            if *from == replica {
+                rights -= *n as i128;
+            }
+        }
+
+        // a state with more transferred away than granted doesn't come
+        // from valid updates, but it must not wrap around into huge rights
+        rights.clamp(0, usize::MAX as i128) as usize
+    }
+
+    pub fn inc(&mut self, replica: ReplicaId) {
+        self.counter.inc(replica);
+    }
+
+    pub fn dec(&mut self, replica: ReplicaId) -> Result<(), InsufficientRights> {
+        self.check_rights(replica, 1)?;
+        self.counter.dec(replica);
+        Ok(())
+    }
+
+    /// Moves rights to decrement from one replica to another
+    pub fn transfer(
+        &mut self,
+        from: ReplicaId,
+        to: ReplicaId,
+        n: usize,
+    ) -> Result<(), InsufficientRights> {
+        self.check_rights(from, n)?;
+        *self.transfers.entry((from, to)).or_default() += n;
+        Ok(())
+    }
+
+    /// Returns the version vector of the counter, the version of a replica
+    /// grows with its increments, decrements and the rights it transfers
+    pub fn clock(&self) -> VClock {
+        self.counter
+            .replicas()
+            .chain(self.transfers.keys().map(|(from, _)| from))
+            .map(|replica| (*replica, clamp(self.version(replica))))
+            .collect()
+    }
+
+    fn version(&self, replica: &ReplicaId) -> i128 {
+        self.transfers
+            .iter()
+            .filter(|((from, _), _)| from == replica)
+            .fold(self.counter.version(replica), |acc, (_, n)| {
+                acc.saturating_add(*n as i128)
+            })
+    }
+
+    fn check_rights(&self, replica: ReplicaId, requested: usize) -> Result<(), InsufficientRights> {
+        let available = self.rights(replica);
+        if available < requested {
+            Err(InsufficientRights {
+                available,
+                requested,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Convergent for BoundedCounter {
+    type Delta = Self;
+
+    fn merge(&mut self, other: Self) {
+        self.counter.merge(other.counter);
+
+        for (k, v2) in other.transfers.into_iter() {
+            self.transfers
+                .entry(k)
+                .and_modify(|v1| *v1 = (*v1).max(v2))
+                .or_insert(v2);
+        }
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) {
+        self.merge(delta);
+    }
+
+    fn take_delta(&mut self) -> Option<Self::Delta> {
+        Some(self.clone())
+    }
+}
+
+impl DeltaSince for BoundedCounter {
+    /// Returns the contributions and transfers of the replicas whose
+    /// versions are greater than their clock entries
+    fn delta_since(&self, clock: &VClock) -> Option<Self::Delta> {
+        let newer = |replica: &ReplicaId| clamp(self.version(replica)) > clock.get(replica);
+        let delta = Self {
+            counter: self.counter.filter(newer),
+            transfers: self
+                .transfers
+                .iter()
+                .filter(|((from, _), _)| newer(from))
+                .map(|(k, n)| (*k, *n))
+                .collect(),
+        };
+        (!delta.counter.is_empty() || !delta.transfers.is_empty()).then_some(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    #[test]
+    fn cannot_go_negative() {
+        let mut counter = BoundedCounter::new();
+        assert_eq!(
+            counter.dec(REPLICA_1),
+            Err(InsufficientRights {
+                available: 0,
+                requested: 1
+            })
+        );
+
+        counter.inc(REPLICA_1);
+        assert_eq!(counter.dec(REPLICA_1), Ok(()));
+        assert!(counter.dec(REPLICA_1).is_err());
+        assert_eq!(counter.value(), 0);
+    }
+
+    #[test]
+    fn rights_are_local() {
+        let mut c1 = BoundedCounter::new();
+        let mut c2 = BoundedCounter::new();
+
+        c1.inc(REPLICA_1);
+        c2.merge(c1.clone());
+
+        // replica 2 sees the value but holds no rights
+        assert_eq!(c2.value(), 1);
+        assert_eq!(c2.rights(REPLICA_2), 0);
+        assert!(c2.dec(REPLICA_2).is_err());
+    }
+
+    #[test]
+    fn transfer_rights() {
+        let mut c1 = BoundedCounter::new();
+        let mut c2 = BoundedCounter::new();
+
+        c1.inc(REPLICA_1);
+        c1.inc(REPLICA_1);
+        c1.inc(REPLICA_1);
+        assert!(c1.transfer(REPLICA_1, REPLICA_2, 4).is_err());
+        assert_eq!(c1.transfer(REPLICA_1, REPLICA_2, 2), Ok(()));
+        assert_eq!(c1.rights(REPLICA_1), 1);
+
+        c2.merge(c1.clone());
+        assert_eq!(c2.rights(REPLICA_2), 2);
+
+        // both replicas consume their rights concurrently
+        c1.dec(REPLICA_1).unwrap();
+        c2.dec(REPLICA_2).unwrap();
+        c2.dec(REPLICA_2).unwrap();
+        assert!(c2.dec(REPLICA_2).is_err());
+
+        c1.merge(c2.clone());
+        c2.merge(c1.clone());
+
+        assert_eq!(c1.value(), 0);
+        assert_eq!(c2.value(), 0);
+        assert_eq!(c1.rights(REPLICA_1), 0);
+        assert_eq!(c1.rights(REPLICA_2), 0);
+    }
+
+    #[test]
+    fn idempotent_merge() {
+        let mut c1 = BoundedCounter::new();
+        let mut c2 = BoundedCounter::new();
+
+        c1.inc(REPLICA_1);
+        c1.transfer(REPLICA_1, REPLICA_2, 1).unwrap();
+
+        c2.merge(c1.clone());
+        c2.merge(c1.clone());
+
+        assert_eq!(c2.value(), 1);
+        assert_eq!(c2.rights(REPLICA_1), 0);
+        assert_eq!(c2.rights(REPLICA_2), 1);
+    }
+
+    #[test]
+    fn rights_never_wrap() {
+        let mut counter = BoundedCounter::new();
+        counter.transfers.insert((REPLICA_1, REPLICA_2), 2);
+        assert_eq!(counter.rights(REPLICA_1), 0);
+        assert_eq!(counter.rights(REPLICA_2), 2);
+    }
+
+    #[test]
+    fn delta_since_clock() {
+        let mut c1 = BoundedCounter::new();
+        let mut c2 = BoundedCounter::new();
+        c1.inc(REPLICA_1);
+        c1.inc(REPLICA_1);
+        c2.merge(c1.clone());
+        assert!(c1.delta_since(&c2.clock()).is_none());
+
+        // a transfer alone advances the clock of the replica making it
+        c1.transfer(REPLICA_1, REPLICA_2, 1).unwrap();
+        let delta = c1.delta_since(&c2.clock()).unwrap();
+        c2.merge_delta(delta);
+        assert_eq!(c2.rights(REPLICA_2), 1);
+        assert!(c1.delta_since(&c2.clock()).is_none());
+
+        c2.dec(REPLICA_2).unwrap();
+        let delta = c2.delta_since(&c1.clock()).unwrap();
+        assert_eq!(delta.counter.get(&REPLICA_1), 0);
+        c1.merge_delta(delta);
+        assert_eq!(c1.value(), 1);
+        assert_eq!(c1.rights(REPLICA_2), 0);
+    }
+}
@@ -0,0 +1,434 @@
+use super::causal::{Dot, DotContext};
+use super::{AWORSet, Convergent, DotKernel};
+use crate::collections::HashMap;
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+use core::ops::Range;
+
+const FANOUT: usize = 16;
+const FANOUT_BITS: usize = 4;
+
+/// Deepest digest, deeper ones would need more hash bits than there are,
+/// or on targets with `usize` narrower than 64 bits, node numbers past it
+pub const MAX_DIGEST_DEPTH: usize = if usize::BITS >= 64 {
+    15
+} else {
+    usize::BITS as usize / FANOUT_BITS - 1
+};
+
+/// Hash tree over the entries of an add-wins set, used to find the entries
+/// two replicas disagree on without shipping the whole set.
+///
+/// Every entry is a dot with its element, hashed into one of `16^depth`
+/// leaves, and a node hash is the XOR of the entry hashes below it.
+/// Replicas compare the children of differing nodes level by level, so
+/// the differing leaves are known after `depth` round trips. Then the
+/// sides swap the dots of their entries under those leaves, and each
+/// sends its entries there as a [`SetDiff`].
+///
+/// Hashes don't depend on the platform, but both replicas have to use
+/// the same depth, around `log16(len)` keeps the leaves small. Depths
+/// above [`MAX_DIGEST_DEPTH`] are clamped, replicas on different targets
+/// have to pick a depth both of them support.
+#[derive(Debug, Clone)]
+pub struct SetDigest {
+    depth: usize,
+    // hashes of the non-empty nodes level by level, starting from the root
+    levels: Vec<HashMap<usize, u64>>,
+    // dots of the entries under every non-empty leaf
+    leaves: HashMap<usize, Vec<Dot>>,
+}
+
+/// Entries of a set under some leaves of its digest along with the dots
+/// of the receiver's entries there which the sender has seen, telling
+/// the receiver which of them have been removed
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SetDiff<K> {
+    leaves: Vec<usize>,
+    entries: Vec<(Dot, K)>,
+    context: DotContext,
+}
+
+impl<K> SetDiff<K> {
+    pub fn entries(&self) -> &[(Dot, K)] {
+        &self.entries
+    }
+}
+
+impl SetDigest {
+    pub fn new<K: Hash>(kernel: &DotKernel<K>, depth: usize) -> Self {
+        let depth = depth.min(MAX_DIGEST_DEPTH);
+        let mut levels: Vec<HashMap<usize, u64>> = (0..=depth).map(|_| HashMap::new()).collect();
+        let mut leaves: HashMap<usize, Vec<Dot>> = HashMap::new();
+
+        for (dot, k) in &kernel.store.0 {
+            let h = entry_hash(dot, k);
+            let leaf = leaf_of(h, depth);
+            leaves.entry(leaf).or_default().push(*dot);
+
+            let mut node = leaf;
+            for level in levels.iter_mut().rev() {
+                *level.entry(node).or_default() ^= h;
+                node /= FANOUT;
+            }
+        }
+
+        Self {
+            depth,
+            levels,
+            leaves,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn root(&self) -> u64 {
+        self.node(0, 0)
+    }
+
+    /// Dots of the entries under the given leaves, to be sent to
+    /// the other replica which builds its diff from them
+    pub fn dots(&self, leaves: &[usize]) -> Vec<Dot> {
+        leaves
+            .iter()
+            .filter_map(|leaf| self.leaves.get(leaf))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Hashes of the children of the given nodes at `level`, in order
+    pub fn children(&self, level: usize, nodes: &[usize]) -> Vec<u64> {
+        nodes
+            .iter()
+            .flat_map(|n| children_of(*n))
+            .map(|child| self.node(level + 1, child))
+            .collect()
+    }
+
+    fn node(&self, level: usize, node: usize) -> u64 {
+        self.levels[level].get(&node).copied().unwrap_or(0)
+    }
+
+    /// Compares the children of the given nodes at `level` with the ones
+    /// received from another replica and returns those which differ,
+    /// the nodes to descend into at the next level
+    pub fn diff_children(&self, level: usize, nodes: &[usize], theirs: &[u64]) -> Vec<usize> {
+        let ours = self.children(level, nodes);
+        let children = nodes.iter().flat_map(|n| children_of(*n));
+
+        children
+            .zip(ours.iter().zip(theirs))
+            .filter(|(_, (a, b))| a != b)
+            .map(|(child, _)| child)
+            .collect()
+    }
+}
+
+impl<K: Eq + Hash + Clone> AWORSet<K> {
+    pub fn digest(&self, depth: usize) -> SetDigest {
+        SetDigest::new(self.kernel(), depth)
+    }
+
+    /// Returns the entries under the leaves of the set's digest which
+    /// differ from another replica, along with which of the dots the
+    /// other replica has under them (`theirs`) this one has seen.
+    /// Entries removed since the digest was taken are skipped.
+    pub fn diff(&self, digest: &SetDigest, leaves: &[usize], theirs: &[Dot]) -> SetDiff<K> {
+        let store = &self.kernel().store.0;
+        let entries = leaves
+            .iter()
+            .filter_map(|leaf| digest.leaves.get(leaf))
+            .flatten()
+            .filter_map(|dot| store.get(dot).map(|k| (*dot, k.clone())))
+            .collect();
+
+        let mut context = DotContext::new();
+        for dot in theirs {
+            if self.kernel().context.contains(dot) {
+                context.add(*dot);
+            }
+        }
+
+        SetDiff {
+            leaves: leaves.to_vec(),
+            entries,
+            context,
+        }
+    }
+
+    /// Merges the entries another replica has under the differing leaves.
+    /// Local entries under those leaves which the other replica has seen
+    /// but no longer has are removed, the rest of the set is left as is.
+    /// The digest has to be taken from the current state of the set.
+    pub fn apply_diff(&mut self, digest: &SetDigest, diff: SetDiff<K>) {
+        let mut delta = DotKernel::new();
+        for (dot, k) in diff.entries {
+            delta.context.add(dot);
+            delta.store.0.insert(dot, k);
+        }
+
+        for leaf in &diff.leaves {
+            let dots = digest.leaves.get(leaf).into_iter().flatten();
+            for dot in dots.filter(|dot| diff.context.contains(dot)) {
+                delta.context.add(*dot);
+            }
+        }
+
+        self.merge_delta(delta);
+    }
+}
+
+fn leaf_of(hash: u64, depth: usize) -> usize {
+    if depth == 0 {
+        0
+    } else {
+        // fits as the depth is capped by the width of `usize`
+        (hash >> (64 - FANOUT_BITS as u64 * depth as u64)) as usize
+    }
+}
+
+/// Children of a node, nodes past the deepest level have none
+fn children_of(node: usize) -> Range<usize> {
+    let first = (node as u64).saturating_mul(FANOUT as u64);
+    let end = first.saturating_add(FANOUT as u64);
+    match (usize::try_from(first), usize::try_from(end)) {
+        (Ok(first), Ok(end)) => first..end,
+        _ => 0..0,
+    }
+}
+
+fn entry_hash<K: Hash>(dot: &Dot, value: &K) -> u64 {
+    let mut hasher = Fnv1a::default();
+    dot.hash(&mut hasher);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// FNV-1a writing integers as little-endian, so hashes
+/// are the same on every replica regardless of platform
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        // spread the low bits into the top ones which pick the leaf
+        let mut h = self.0;
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        h
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, n: u16) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, n: u128) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReplicaId;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    /// Runs the digest exchange between the two sets, returning the
+    /// number of round trips and the number of entries shipped
+    fn sync(a: &mut AWORSet<u32>, b: &mut AWORSet<u32>, depth: usize) -> (usize, usize) {
+        let da = a.digest(depth);
+        let db = b.digest(depth);
+
+        let mut round_trips = 0;
+        let mut nodes = if da.root() == db.root() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        for level in 0..depth {
+            if nodes.is_empty() {
+                break;
+            }
+            let theirs = db.children(level, &nodes);
+            nodes = da.diff_children(level, &nodes, &theirs);
+            round_trips += 1;
+        }
+
+        let to_a = b.diff(&db, &nodes, &da.dots(&nodes));
+        let to_b = a.diff(&da, &nodes, &db.dots(&nodes));
+        let shipped = to_a.entries().len() + to_b.entries().len();
+
+        a.apply_diff(&da, to_a);
+        b.apply_diff(&db, to_b);
+        (round_trips, shipped)
+    }
+
+    fn sorted(set: &AWORSet<u32>) -> Vec<u32> {
+        let mut keys: Vec<_> = set.keys().copied().collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn equal_sets_have_equal_roots() {
+        let mut a = AWORSet::new(REPLICA_1);
+        for i in 0..100 {
+            a.add(i);
+        }
+        let mut b = AWORSet::new(REPLICA_2);
+        b.merge(a.clone());
+
+        assert_eq!(a.digest(2).root(), b.digest(2).root());
+        assert_eq!(sync(&mut a, &mut b, 2), (0, 0));
+    }
+
+    #[test]
+    fn ships_only_differing_entries() {
+        let mut a = AWORSet::new(REPLICA_1);
+        for i in 0..10_000 {
+            a.add(i);
+        }
+        let mut b = AWORSet::new(REPLICA_2);
+        b.merge(a.clone());
+
+        a.add(20_000);
+        b.add(30_000);
+        b.remove(&5);
+        a.remove(&6);
+
+        let (round_trips, shipped) = sync(&mut a, &mut b, 3);
+        assert_eq!(round_trips, 3);
+        assert!(shipped < 100, "shipped {} entries", shipped);
+
+        assert_eq!(sorted(&a), sorted(&b));
+        assert!(a.contains(&20_000) && a.contains(&30_000));
+        assert!(!a.contains(&5) && !b.contains(&6));
+        assert_eq!(a.digest(3).root(), b.digest(3).root());
+    }
+
+    #[test]
+    fn concurrent_add_wins() {
+        let mut a = AWORSet::new(REPLICA_1);
+        a.add(1);
+        let mut b = AWORSet::new(REPLICA_2);
+        b.merge(a.clone());
+
+        a.remove(&1);
+        b.add(1);
+
+        sync(&mut a, &mut b, 1);
+        assert!(a.contains(&1));
+        assert!(b.contains(&1));
+        assert_eq!(sorted(&a), sorted(&b));
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        assert_eq!(
+            entry_hash(&Dot(1, 2), &"foo"),
+            entry_hash(&Dot(1, 2), &"foo")
+        );
+        assert_ne!(
+            entry_hash(&Dot(1, 2), &"foo"),
+            entry_hash(&Dot(1, 3), &"foo")
+        );
+        // has to be the same on every platform
+        assert_eq!(entry_hash(&Dot(1, 2), &"foo"), 3461795696438910810);
+        assert_eq!(leaf_of(u64::MAX, 2), 255);
+        assert_eq!(leaf_of(u64::MAX, 0), 0);
+    }
+
+    #[test]
+    fn depth_is_clamped() {
+        let mut a = AWORSet::new(REPLICA_1);
+        let mut b = AWORSet::new(REPLICA_2);
+        for i in 0..100 {
+            a.add(i);
+            b.add(i + 50);
+        }
+
+        let digest = a.digest(64);
+        assert_eq!(digest.depth(), MAX_DIGEST_DEPTH);
+        assert!(digest.levels.iter().all(|l| l.len() <= 100));
+
+        sync(&mut a, &mut b, MAX_DIGEST_DEPTH);
+        assert_eq!(sorted(&a), sorted(&b));
+        assert_eq!(sorted(&a).len(), 150);
+    }
+
+    #[test]
+    fn diff_skips_stale_entries() {
+        let mut a = AWORSet::new(REPLICA_1);
+        for i in 0..10 {
+            a.add(i);
+        }
+        let digest = a.digest(1);
+        a.remove(&3);
+
+        let leaves: Vec<_> = (0..16).collect();
+        let diff = a.diff(&digest, &leaves, &[]);
+        assert_eq!(diff.entries().len(), 9);
+        assert!(diff.entries().iter().all(|(_, k)| *k != 3));
+    }
+
+    #[test]
+    fn diff_ships_context_of_their_dots_only() {
+        let mut a = AWORSet::new(REPLICA_1);
+        for i in 0..1000 {
+            a.add(i);
+        }
+        let mut b = AWORSet::new(REPLICA_2);
+        b.merge(a.clone());
+        b.add(5000);
+
+        let dot = b.dots(&5000).next().unwrap();
+        let leaves = [leaf_of(entry_hash(&dot, &5000), 2)];
+        let theirs = b.digest(2).dots(&leaves);
+        let diff = a.diff(&a.digest(2), &leaves, &theirs);
+
+        // a has seen every dot of b under the leaf but the new one
+        assert_eq!(diff.context.len(), theirs.len() - 1);
+        assert!(!diff.context.contains(&dot));
+    }
+
+    #[test]
+    fn children_past_deepest_level() {
+        assert_eq!(children_of(1), 16..32);
+        assert!(children_of(usize::MAX).is_empty());
+        assert_eq!(
+            leaf_of(u64::MAX, MAX_DIGEST_DEPTH),
+            usize::try_from((1u64 << (FANOUT_BITS * MAX_DIGEST_DEPTH)) - 1).unwrap()
+        );
+    }
+}
@@ -1,24 +1,137 @@
-use super::{Convergent, ReplicaId};
-use std::collections::HashMap;
+use super::{Convergent, DeltaSince, ReplicaId};
+use crate::collections::HashMap;
+use crate::vclock::VClock;
+use core::fmt;
+use core::hash::Hash;
+
+/// Error returned when a counter operation exceeds the range of its integer type
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Overflow;
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "counter overflow")
+    }
+}
+
+impl core::error::Error for Overflow {}
+
+/// Unsigned integer type which can be used as a counter value
+pub trait CounterValue: Copy + Default + Ord + Hash + fmt::Debug + TryInto<i128> {
+    const ONE: Self;
+    const MAX: Self;
+
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    fn saturating_add(self, other: Self) -> Self;
+}
+
+macro_rules! impl_counter_value {
+    ($($t:ty),*) => {
+        $(
+            impl CounterValue for $t {
+                const ONE: Self = 1;
+                const MAX: Self = <$t>::MAX;
+
+                fn checked_add(self, other: Self) -> Option<Self> {
+                    <$t>::checked_add(self, other)
+                }
+
+                fn saturating_add(self, other: Self) -> Self {
+                    <$t>::saturating_add(self, other)
+                }
+            }
+        )*
+    };
+}
+
+impl_counter_value!(u8, u16, u32, u64, u128, usize);
 
 #[derive(Debug, Clone, Default)]
-pub struct GCounter(HashMap<ReplicaId, usize>);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GCounter<T = usize>(HashMap<ReplicaId, T>);
 
-impl GCounter {
+impl<T: CounterValue> GCounter<T> {
     pub fn new() -> Self {
-        Default::default()
+        Self(HashMap::new())
+    }
+
+    /// Returns the sum of all contributions, saturating at the maximum value
+    pub fn value(&self) -> T {
+        self.0
+            .values()
+            .fold(T::default(), |acc, v| acc.saturating_add(*v))
     }
 
-    pub fn value(&self) -> usize {
-        self.0.values().sum()
+    pub fn checked_value(&self) -> Result<T, Overflow> {
+        self.0
+            .values()
+            .try_fold(T::default(), |acc, v| acc.checked_add(*v))
+            .ok_or(Overflow)
+    }
+
+    pub fn get(&self, replica: &ReplicaId) -> T {
+        self.0.get(replica).copied().unwrap_or_default()
+    }
+
+    pub fn inc(&mut self, replica: ReplicaId) -> Result<(), Overflow> {
+        self.add(replica, T::ONE)
+    }
+
+    /// Adds `n` to the replica's contribution, leaving
+    /// the counter unchanged if it would overflow
+    pub fn add(&mut self, replica: ReplicaId, n: T) -> Result<(), Overflow> {
+        let v = self.0.entry(replica).or_default();
+        *v = v.checked_add(n).ok_or(Overflow)?;
+        Ok(())
+    }
+
+    /// Adds `n` to the replica's contribution, clamping it at the maximum value
+    pub fn saturating_add(&mut self, replica: ReplicaId, n: T) {
+        let v = self.0.entry(replica).or_default();
+        *v = v.saturating_add(n);
+    }
+
+    /// Returns the version vector of the counter. Only the owner grows its
+    /// contribution, so the contributions themselves serve as versions.
+    /// Contributions beyond the range of a clock entry are clamped to it.
+    pub fn clock(&self) -> VClock {
+        self.0
+            .iter()
+            .map(|(replica, v)| (*replica, clamp(version(*v))))
+            .collect()
+    }
+
+    pub(super) fn replicas(&self) -> impl Iterator<Item = &ReplicaId> {
+        self.0.keys()
+    }
+
+    /// Returns the contributions of the replicas matching the predicate
+    pub(super) fn filter(&self, f: impl Fn(&ReplicaId) -> bool) -> Self {
+        Self(
+            self.0
+                .iter()
+                .filter(|(replica, _)| f(replica))
+                .map(|(replica, v)| (*replica, *v))
+                .collect(),
+        )
     }
 
-    pub fn inc(&mut self, replica: ReplicaId) {
-        self.0.entry(replica).and_modify(|v| *v += 1).or_insert(1);
+    pub(super) fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 }
 
-impl Convergent for GCounter {
+/// Converts a contribution into a version comparable with a clock entry
+pub(super) fn version<T: CounterValue>(v: T) -> i128 {
+    v.try_into().unwrap_or(i128::MAX)
+}
+
+pub(super) fn clamp(version: i128) -> usize {
+    version.try_into().unwrap_or(usize::MAX)
+}
+
+impl<T: CounterValue> Convergent for GCounter<T> {
     type Delta = Self;
 
     fn merge(&mut self, other: Self) {
@@ -39,6 +152,15 @@ impl Convergent for GCounter {
     }
 }
 
+impl<T: CounterValue> DeltaSince for GCounter<T> {
+    /// Returns the contributions greater than the clock entries of their
+    /// replicas, compared in the clamped range of the clock
+    fn delta_since(&self, clock: &VClock) -> Option<Self::Delta> {
+        let delta = self.filter(|replica| clamp(version(self.get(replica))) > clock.get(replica));
+        (!delta.is_empty()).then_some(delta)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -48,26 +170,26 @@ mod tests {
 
     #[test]
     fn initial_value_is_zero() {
-        let counter = GCounter::new();
+        let counter = GCounter::<usize>::new();
         assert_eq!(counter.value(), 0);
     }
 
     #[test]
     fn increment() {
-        let mut counter = GCounter::new();
-        counter.inc(REPLICA_1);
+        let mut counter = GCounter::<usize>::new();
+        counter.inc(REPLICA_1).unwrap();
         assert_eq!(counter.value(), 1);
     }
 
     #[test]
     fn merge_1() {
-        let mut counter1 = GCounter::new();
+        let mut counter1 = GCounter::<usize>::new();
         let mut counter2 = GCounter::new();
 
         // counter1 does one increment, counter2 does two increments
-        counter1.inc(REPLICA_1);
-        counter2.inc(REPLICA_2);
-        counter2.inc(REPLICA_2);
+        counter1.inc(REPLICA_1).unwrap();
+        counter2.inc(REPLICA_2).unwrap();
+        counter2.inc(REPLICA_2).unwrap();
 
         // Now, merge counter2 into counter1
         counter1.merge(counter2);
@@ -78,12 +200,12 @@ mod tests {
 
     #[test]
     fn merge_2() {
-        let mut counter1 = GCounter::new();
+        let mut counter1 = GCounter::<usize>::new();
         let mut counter2 = GCounter::new();
 
         // both counters increment the same replica
-        counter1.inc(REPLICA_1);
-        counter2.inc(REPLICA_1);
+        counter1.inc(REPLICA_1).unwrap();
+        counter2.inc(REPLICA_1).unwrap();
 
         // Merge counter2 into counter1
         counter1.merge(counter2);
@@ -93,17 +215,88 @@ mod tests {
 
     #[test]
     fn merge_3() {
-        let mut counter1 = GCounter::new();
+        let mut counter1 = GCounter::<usize>::new();
         let mut counter2 = GCounter::new();
 
         // both counters increment the same replica
-        counter1.inc(REPLICA_1);
-        counter2.inc(REPLICA_1);
-        counter2.inc(REPLICA_1);
+        counter1.inc(REPLICA_1).unwrap();
+        counter2.inc(REPLICA_1).unwrap();
+        counter2.inc(REPLICA_1).unwrap();
 
         // Merge counter2 into counter1
         counter1.merge(counter2);
 
         assert_eq!(counter1.value(), 2);
     }
+
+    #[test]
+    fn add_custom_step() {
+        let mut counter = GCounter::<usize>::new();
+        counter.add(REPLICA_1, 5).unwrap();
+        counter.add(REPLICA_2, 3).unwrap();
+        counter.inc(REPLICA_1).unwrap();
+        assert_eq!(counter.get(&REPLICA_1), 6);
+        assert_eq!(counter.value(), 9);
+    }
+
+    #[test]
+    fn add_overflow() {
+        let mut counter = GCounter::<u8>::default();
+        counter.add(REPLICA_1, 250).unwrap();
+        assert_eq!(counter.add(REPLICA_1, 10), Err(Overflow));
+        assert_eq!(counter.get(&REPLICA_1), 250);
+
+        counter.saturating_add(REPLICA_1, 10);
+        assert_eq!(counter.get(&REPLICA_1), u8::MAX);
+    }
+
+    #[test]
+    fn value_overflow() {
+        let mut counter = GCounter::<u32>::default();
+        counter.add(REPLICA_1, u32::MAX).unwrap();
+        counter.add(REPLICA_2, 1).unwrap();
+
+        assert_eq!(counter.checked_value(), Err(Overflow));
+        assert_eq!(counter.value(), u32::MAX);
+    }
+
+    #[test]
+    fn delta_since_clock() {
+        let mut counter1 = GCounter::<usize>::new();
+        counter1.add(REPLICA_1, 5).unwrap();
+        counter1.inc(REPLICA_2).unwrap();
+
+        let mut counter2 = GCounter::<usize>::new();
+        counter2.merge(counter1.clone());
+        let clock = counter2.clock();
+        assert_eq!(clock.get(&REPLICA_1), 5);
+        assert!(counter1.delta_since(&clock).is_none());
+
+        counter1.inc(REPLICA_1).unwrap();
+        let delta = counter1.delta_since(&clock).unwrap();
+        assert_eq!(delta.get(&REPLICA_1), 6);
+        assert_eq!(delta.get(&REPLICA_2), 0);
+
+        counter2.merge_delta(delta);
+        assert_eq!(counter2.value(), 7);
+    }
+
+    #[test]
+    fn inc_overflow() {
+        let mut counter = GCounter::<u8>::new();
+        counter.add(REPLICA_1, u8::MAX).unwrap();
+        assert_eq!(counter.inc(REPLICA_1), Err(Overflow));
+        assert_eq!(counter.get(&REPLICA_1), u8::MAX);
+    }
+
+    #[test]
+    fn delta_since_beyond_clock_range() {
+        let mut counter1 = GCounter::<u128>::new();
+        counter1.add(REPLICA_1, u128::MAX).unwrap();
+
+        let clock = counter1.clock();
+        assert_eq!(clock.get(&REPLICA_1), usize::MAX);
+        assert!(counter1.delta_since(&clock).is_none());
+        assert!(counter1.delta_since(&VClock::new()).is_some());
+    }
 }
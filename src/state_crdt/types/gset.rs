@@ -0,0 +1,124 @@
+use super::Convergent;
+use crate::collections::HashSet;
+use core::borrow::Borrow;
+use core::hash::Hash;
+
+/// Grow-only set
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "T: serde::Serialize + Eq + Hash",
+        deserialize = "T: serde::Deserialize<'de> + Eq + Hash"
+    ))
+)]
+pub struct GSet<T> {
+    items: HashSet<T>,
+    delta: Option<HashSet<T>>,
+}
+
+impl<T> Default for GSet<T> {
+    fn default() -> Self {
+        Self {
+            items: HashSet::new(),
+            delta: None,
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> GSet<T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.items.contains(value)
+    }
+
+    pub fn get<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.items.get(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn add(&mut self, value: T) {
+        if self.items.insert(value.clone()) {
+            self.delta.get_or_insert_default().insert(value);
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> Convergent for GSet<T> {
+    type Delta = HashSet<T>;
+
+    fn merge(&mut self, other: Self) {
+        self.items.extend(other.items);
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) {
+        for value in delta {
+            self.add(value);
+        }
+    }
+
+    fn take_delta(&mut self) -> Option<Self::Delta> {
+        self.delta.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_and_merge() {
+        let mut s1 = GSet::new();
+        let mut s2 = GSet::new();
+
+        s1.add("foo");
+        s2.add("bar");
+        s2.add("foo");
+
+        s1.merge(s2.clone());
+
+        assert!(s1.contains("foo"));
+        assert!(s1.contains("bar"));
+        assert_eq!(s1.len(), 2);
+    }
+
+    #[test]
+    fn delta_sync() {
+        let mut s1 = GSet::new();
+        let mut s2 = GSet::new();
+
+        s1.add("foo");
+        s1.add("foo");
+        assert_eq!(s1.take_delta(), Some(HashSet::from(["foo"])));
+        assert_eq!(s1.take_delta(), None);
+
+        s1.add("bar");
+        s2.merge_delta(s1.take_delta().unwrap());
+
+        assert!(!s2.contains("foo"));
+        assert!(s2.contains("bar"));
+    }
+}
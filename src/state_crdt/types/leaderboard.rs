@@ -0,0 +1,307 @@
+use super::{AWORMap, AWORMapDelta, Convergent, MaxRegister, ReplicaId};
+use crate::collections::{BTreeSet, HashMap};
+use core::borrow::Borrow;
+use core::cmp::Reverse;
+use core::hash::Hash;
+
+/// Add-wins map of keys to their best scores, with an ordered index
+/// kept up to date on every update so that queries don't need to sort.
+/// Merges only reindex the keys they have changed.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        from = "LeaderboardState<K, S>",
+        into = "LeaderboardState<K, S>",
+        bound(
+            serialize = "K: serde::Serialize + Eq + Hash + Clone, S: serde::Serialize + Clone",
+            deserialize = "K: serde::Deserialize<'de> + Eq + Hash + Ord + Clone, \
+                           S: serde::Deserialize<'de> + Ord + Clone"
+        )
+    )
+)]
+pub struct Leaderboard<K, S> {
+    entries: AWORMap<K, MaxRegister<S>>,
+    index: BTreeSet<(Reverse<S>, K)>,
+    // scores the keys are indexed under, which locate them in the index
+    indexed: HashMap<K, S>,
+}
+
+impl<K: Eq + Hash + Ord + Clone, S: Ord + Clone> Leaderboard<K, S> {
+    pub fn new(replica_id: ReplicaId) -> Self {
+        Self {
+            entries: AWORMap::new(replica_id),
+            index: BTreeSet::new(),
+            indexed: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn score<Q>(&self, key: &Q) -> Option<&S>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.get(key).and_then(|r| r.value())
+    }
+
+    /// Records the score for the key, keeping the best one seen so far
+    pub fn submit(&mut self, key: K, score: S) {
+        self.entries.update(key.clone(), |r| r.set(score));
+        self.reindex(&key);
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if let Some((k, score)) = self.indexed.remove_entry(key) {
+            self.index.remove(&(Reverse(score), k));
+        }
+        self.entries.remove(key);
+    }
+
+    /// Returns entries with the highest scores, ties are ordered by key
+    pub fn top_n(&self, n: usize) -> impl Iterator<Item = (&K, &S)> {
+        self.index.iter().take(n).map(|(s, k)| (k, &s.0))
+    }
+
+    /// Returns the 1-based position of the key in the leaderboard
+    pub fn rank<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let (key, score) = self.entries.get_key_value(key)?;
+        let score = score.value()?;
+        let ahead = self
+            .index
+            .range(..(Reverse(score.clone()), key.clone()))
+            .count();
+        Some(ahead + 1)
+    }
+
+    /// Moves the key in the index from its old score to the current one
+    fn reindex(&mut self, key: &K) {
+        let score = self.score(key);
+        if self.indexed.get(key) == score {
+            return;
+        }
+        let score = score.cloned();
+
+        if let Some(old) = self.indexed.remove(key) {
+            self.index.remove(&(Reverse(old), key.clone()));
+        }
+        if let Some(score) = score {
+            self.index.insert((Reverse(score.clone()), key.clone()));
+            self.indexed.insert(key.clone(), score);
+        }
+    }
+}
+
+/// Serialized form of the leaderboard, the score index is rebuilt on load
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(bound(
+    serialize = "K: serde::Serialize + Eq + Hash + Clone, S: serde::Serialize",
+    deserialize = "K: serde::Deserialize<'de> + Eq + Hash + Clone, S: serde::Deserialize<'de>"
+))]
+struct LeaderboardState<K, S> {
+    entries: AWORMap<K, MaxRegister<S>>,
+}
+
+#[cfg(feature = "serde")]
+impl<K, S> From<Leaderboard<K, S>> for LeaderboardState<K, S> {
+    fn from(board: Leaderboard<K, S>) -> Self {
+        Self {
+            entries: board.entries,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K: Eq + Hash + Ord + Clone, S: Ord + Clone> From<LeaderboardState<K, S>>
+    for Leaderboard<K, S>
+{
+    fn from(s: LeaderboardState<K, S>) -> Self {
+        let indexed: HashMap<K, S> = s
+            .entries
+            .iter()
+            .filter_map(|(k, r)| Some((k.clone(), r.value()?.clone())))
+            .collect();
+        let index = indexed
+            .iter()
+            .map(|(k, score)| (Reverse(score.clone()), k.clone()))
+            .collect();
+        Self {
+            entries: s.entries,
+            index,
+            indexed,
+        }
+    }
+}
+
+impl<K: Eq + Hash + Ord + Clone, S: Ord + Clone> Convergent for Leaderboard<K, S> {
+    type Delta = AWORMapDelta<K, S>;
+
+    fn merge(&mut self, other: Self) {
+        for key in self.entries.merge_tracked(other.entries) {
+            self.reindex(&key);
+        }
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) {
+        for key in self.entries.merge_delta_tracked(delta) {
+            self.reindex(&key);
+        }
+    }
+
+    fn take_delta(&mut self) -> Option<Self::Delta> {
+        self.entries.take_delta()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    #[test]
+    fn top_n_and_rank() {
+        let mut b1 = Leaderboard::new(REPLICA_1);
+        b1.submit("alice", 10);
+        b1.submit("bob", 30);
+        b1.submit("carol", 20);
+        b1.submit("dave", 20);
+
+        let top: Vec<_> = b1.top_n(3).collect();
+        assert_eq!(top, vec![(&"bob", &30), (&"carol", &20), (&"dave", &20)]);
+
+        assert_eq!(b1.rank("bob"), Some(1));
+        assert_eq!(b1.rank("dave"), Some(3));
+        assert_eq!(b1.rank("alice"), Some(4));
+        assert_eq!(b1.rank("eve"), None);
+    }
+
+    #[test]
+    fn keeps_best_score() {
+        let mut b1 = Leaderboard::new(REPLICA_1);
+        b1.submit("alice", 10);
+        b1.submit("bob", 15);
+        b1.submit("alice", 5);
+        assert_eq!(b1.score("alice"), Some(&10));
+        assert_eq!(b1.rank("alice"), Some(2));
+
+        b1.submit("alice", 25);
+        assert_eq!(b1.rank("alice"), Some(1));
+        assert_eq!(b1.len(), 2);
+    }
+
+    #[test]
+    fn remove() {
+        let mut b1 = Leaderboard::new(REPLICA_1);
+        b1.submit("alice", 10);
+        b1.submit("bob", 15);
+        b1.remove("bob");
+
+        assert_eq!(b1.rank("bob"), None);
+        assert_eq!(b1.rank("alice"), Some(1));
+        assert_eq!(b1.len(), 1);
+    }
+
+    #[test]
+    fn merge_keeps_index_in_sync() {
+        let mut b1 = Leaderboard::new(REPLICA_1);
+        let mut b2 = Leaderboard::new(REPLICA_2);
+
+        b1.submit("alice", 10);
+        b1.submit("bob", 15);
+        b2.merge(b1.clone());
+
+        b1.submit("alice", 40);
+        b2.remove("bob");
+        b2.submit("carol", 20);
+
+        b1.merge(b2.clone());
+        b2.merge(b1.clone());
+
+        let top1: Vec<_> = b1.top_n(10).collect();
+        let top2: Vec<_> = b2.top_n(10).collect();
+        assert_eq!(top1, vec![(&"alice", &40), (&"carol", &20)]);
+        assert_eq!(top1, top2);
+    }
+
+    #[test]
+    fn delta_sync() {
+        let mut b1 = Leaderboard::new(REPLICA_1);
+        let mut b2 = Leaderboard::new(REPLICA_2);
+
+        b1.submit("alice".to_owned(), 10);
+        b2.submit("bob".to_owned(), 15);
+
+        b2.merge_delta(b1.take_delta().unwrap());
+        assert_eq!(b2.rank("alice"), Some(2));
+
+        b2.submit("alice".to_owned(), 20);
+        b1.merge_delta(b2.take_delta().unwrap());
+        assert_eq!(b1.rank("alice"), Some(1));
+        assert_eq!(b1.rank("bob"), Some(2));
+    }
+
+    #[test]
+    fn relayed_removal() {
+        let mut b1 = Leaderboard::new(REPLICA_1);
+        let mut b2 = Leaderboard::new(REPLICA_2);
+        let mut b3 = Leaderboard::new(789);
+
+        b1.submit("alice", 10);
+        b1.submit("bob", 15);
+        let delta = b1.take_delta().unwrap();
+        b2.merge_delta(delta.clone());
+        b3.merge_delta(delta);
+        b2.take_delta();
+
+        // b3 only hears of the removal made by b1 through b2
+        b1.remove("bob");
+        b2.merge_delta(b1.take_delta().unwrap());
+        b3.merge_delta(b2.take_delta().unwrap());
+
+        assert_eq!(b3.rank("bob"), None);
+        assert_eq!(b3.top_n(10).collect::<Vec<_>>(), vec![(&"alice", &10)]);
+    }
+
+    #[test]
+    fn submit_without_change_survives_concurrent_remove() {
+        let mut b1 = Leaderboard::new(REPLICA_1);
+        let mut b2 = Leaderboard::new(REPLICA_2);
+
+        b1.submit("alice", 40);
+        b2.merge_delta(b1.take_delta().unwrap());
+
+        // b2 removes alice while b1 submits a score below her best
+        b2.remove("alice");
+        b1.submit("alice", 20);
+
+        let d1 = b1.take_delta().unwrap();
+        let d2 = b2.take_delta().unwrap();
+        b1.merge_delta(d2);
+        b2.merge_delta(d1);
+
+        let top1: Vec<_> = b1.top_n(10).collect();
+        let top2: Vec<_> = b2.top_n(10).collect();
+        assert_eq!(top1, vec![(&"alice", &40)]);
+        assert_eq!(top1, top2);
+        assert_eq!(b2.rank("alice"), Some(1));
+    }
+}
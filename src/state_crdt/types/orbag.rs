@@ -0,0 +1,305 @@
+use super::causal::{Causal, DotFun, DotMap, DotStore};
+use super::{Convergent, DeltaSince, FromReplica, ReplicaId};
+use crate::vclock::VClock;
+use core::borrow::Borrow;
+use core::hash::Hash;
+
+type BagKernel<K> = Causal<DotMap<K, DotFun<usize>>>;
+
+/// Observed-remove multiset. Every replica keeps its count of an element
+/// under a single dot, which it replaces on each addition. Removing an
+/// element drops the counts the replica has observed, a replica adding
+/// concurrently keeps its whole count as it lives under a dot the removal
+/// hasn't seen.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "K: serde::Serialize + Eq + Hash",
+        deserialize = "K: serde::Deserialize<'de> + Eq + Hash"
+    ))
+)]
+pub struct ORBag<K> {
+    replica_id: ReplicaId,
+    state: BagKernel<K>,
+    delta: Option<BagKernel<K>>,
+}
+
+impl<K: Eq + Hash> PartialEq for ORBag<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.replica_id == other.replica_id
+            && self.state == other.state
+            && self.delta == other.delta
+    }
+}
+
+impl<K: Eq + Hash> Eq for ORBag<K> {}
+
+impl<K: Eq + Hash + Clone> ORBag<K> {
+    pub fn new(replica_id: ReplicaId) -> Self {
+        Self {
+            replica_id,
+            state: Causal::new(),
+            delta: None,
+        }
+    }
+
+    pub fn count<Q>(&self, value: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.state.store.0.get(value).map_or(0, |counts| {
+            counts.0.values().fold(0, |acc, n| acc.saturating_add(*n))
+        })
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.count(value) > 0
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.state.store.0.keys()
+    }
+
+    /// Returns the version vector of the bag
+    pub fn clock(&self) -> VClock {
+        self.state.context.clock()
+    }
+
+    /// Adds `n` occurrences of the element, adding none is a no-op
+    pub fn add(&mut self, value: K, n: usize) {
+        if n == 0 {
+            return;
+        }
+        let dot = self.state.context.next_dot(self.replica_id);
+        let counts = self.state.store.0.entry(value.clone()).or_default();
+        let old = counts.dots().find(|d| d.0 == self.replica_id);
+        let n = old.map_or(n, |d| counts.0[&d].saturating_add(n));
+
+        let delta = self.delta.get_or_insert_default();
+        let delta_counts = delta.store.0.entry(value).or_default();
+        if let Some(old) = old {
+            counts.0.remove(&old);
+            delta_counts.0.remove(&old);
+            delta.context.add(old);
+        }
+        counts.0.insert(dot, n);
+        delta_counts.0.insert(dot, n);
+        delta.context.add(dot);
+    }
+
+    /// Removes all observed occurrences of the element. The removal is
+    /// recorded under a dot of the replica, so replicas catching up from
+    /// a clock learn of it.
+    pub fn remove<Q>(&mut self, value: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let Some(counts) = self.state.store.0.remove(value) else {
+            return;
+        };
+        let marker = self.state.context.next_dot(self.replica_id);
+
+        let delta = self.delta.get_or_insert_default();
+        for dot in counts.dots() {
+            delta.context.add(dot);
+        }
+        delta.context.add(marker);
+        delta.store.0.remove(value);
+    }
+}
+
+impl<K: Eq + Hash + Clone> FromReplica for ORBag<K> {
+    fn from_replica(replica_id: ReplicaId) -> Self {
+        Self::new(replica_id)
+    }
+}
+
+impl<K: Eq + Hash + Clone> Convergent for ORBag<K> {
+    type Delta = BagKernel<K>;
+
+    fn merge(&mut self, other: Self) {
+        if let Some(delta) = other.delta {
+            let d = self.delta.get_or_insert_default();
+            d.join(delta);
+        }
+
+        self.state.join(other.state);
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) {
+        self.state.join(delta.clone());
+        self.delta.get_or_insert_default().join(delta);
+    }
+
+    fn take_delta(&mut self) -> Option<Self::Delta> {
+        self.delta.take()
+    }
+
+    fn removal_delta(&self) -> Option<Self::Delta> {
+        Some(self.state.removal())
+    }
+}
+
+impl<K: Eq + Hash + Clone> DeltaSince for ORBag<K> {
+    /// Returns the counts added under dots not covered by the clock,
+    /// along with the ones removed since
+    fn delta_since(&self, clock: &VClock) -> Option<Self::Delta> {
+        self.state.since(clock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "maps")]
+    use super::super::AWORMap;
+    use super::*;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    #[test]
+    fn add_and_count() {
+        let mut b1 = ORBag::new(REPLICA_1);
+        assert_eq!(b1.count("foo"), 0);
+
+        b1.add("foo", 2);
+        b1.add("foo", 3);
+        b1.add("bar", 1);
+
+        assert_eq!(b1.count("foo"), 5);
+        assert_eq!(b1.count("bar"), 1);
+        assert_eq!(b1.state.store.0["foo"].0.len(), 1);
+
+        // adding nothing doesn't make the element appear
+        b1.add("baz", 0);
+        assert!(!b1.contains("baz"));
+        assert_eq!(b1.keys().count(), 2);
+    }
+
+    #[test]
+    fn remove() {
+        let mut b1 = ORBag::new(REPLICA_1);
+        b1.add("foo", 2);
+        b1.remove("foo");
+
+        assert!(!b1.contains("foo"));
+        assert_eq!(b1.keys().count(), 0);
+
+        // removing an absent element has nothing to ship
+        b1.take_delta();
+        b1.remove("foo");
+        assert_eq!(b1.take_delta(), None);
+    }
+
+    #[test]
+    fn merge_counts() {
+        let mut b1 = ORBag::new(REPLICA_1);
+        let mut b2 = ORBag::new(REPLICA_2);
+
+        b1.add("foo", 2);
+        b2.add("foo", 3);
+        b1.merge(b2.clone());
+        b2.merge(b1.clone());
+
+        assert_eq!(b1.count("foo"), 5);
+        assert_eq!(b2.count("foo"), 5);
+
+        // merging the same state again doesn't change the counts
+        b1.merge(b2.clone());
+        assert_eq!(b1.count("foo"), 5);
+    }
+
+    #[test]
+    fn observed_remove() {
+        let mut b1 = ORBag::new(REPLICA_1);
+        let mut b2 = ORBag::new(REPLICA_2);
+
+        b1.add("foo", 2);
+        b2.merge(b1.clone());
+
+        // b2 removes what it has seen, b1 concurrently adds more
+        b2.remove("foo");
+        b1.add("foo", 1);
+
+        b1.merge(b2.clone());
+        b2.merge(b1.clone());
+
+        // b1's count was replaced under a dot the removal hasn't seen
+        assert_eq!(b1.count("foo"), 3);
+        assert_eq!(b2.count("foo"), 3);
+
+        // a removal which has seen every count drops the element
+        b2.remove("foo");
+        b1.merge(b2.clone());
+        assert!(!b1.contains("foo"));
+    }
+
+    #[test]
+    fn delta_sync() {
+        let mut b1 = ORBag::new(REPLICA_1);
+        let mut b2 = ORBag::new(REPLICA_2);
+
+        b1.add("foo", 2);
+        b2.merge_delta(b1.take_delta().unwrap());
+        assert_eq!(b2.count("foo"), 2);
+
+        b2.add("foo", 1);
+        b2.remove("foo");
+        b2.add("bar", 4);
+        b1.add("foo", 5);
+
+        let d1 = b1.take_delta().unwrap();
+        let d2 = b2.take_delta().unwrap();
+        b1.merge_delta(d2);
+        b2.merge_delta(d1);
+
+        // b1's concurrent addition keeps its whole count
+        assert_eq!(b1.count("foo"), 7);
+        assert_eq!(b2.count("foo"), 7);
+        assert_eq!(b1.count("bar"), 4);
+        assert_eq!(b2.count("bar"), 4);
+    }
+
+    #[test]
+    #[cfg(feature = "maps")]
+    fn nested_in_map() {
+        let mut m1: AWORMap<String, ORBag<&str>> = AWORMap::new(REPLICA_1);
+        let mut m2: AWORMap<String, ORBag<&str>> = AWORMap::new(REPLICA_2);
+
+        m1.update("cart".to_owned(), |b| b.add("apple", 2));
+        m2.update("cart".to_owned(), |b| b.add("apple", 1));
+
+        let d1 = m1.take_delta().unwrap();
+        let d2 = m2.take_delta().unwrap();
+        m1.merge_delta(d2);
+        m2.merge_delta(d1);
+
+        for m in [&m1, &m2] {
+            assert_eq!(m.get("cart").unwrap().count("apple"), 3);
+        }
+    }
+
+    #[test]
+    fn delta_since_after_remove() {
+        let mut b1 = ORBag::new(REPLICA_1);
+        let mut b2 = ORBag::new(REPLICA_2);
+        b1.add("foo", 2);
+        b1.add("bar", 1);
+        b2.merge(b1.clone());
+        assert!(b1.delta_since(&b2.clock()).is_none());
+
+        b1.remove("foo");
+        b1.add("bar", 3);
+        b2.merge_delta(b1.delta_since(&b2.clock()).unwrap());
+        assert!(!b2.contains("foo"));
+        assert_eq!(b2.count("bar"), 4);
+    }
+}
@@ -1,30 +1,99 @@
-use super::{Convergent, GCounter, ReplicaId};
+use super::gcounter::{clamp, version};
+use super::{Convergent, CounterValue, DeltaSince, GCounter, Overflow, ReplicaId};
+use crate::vclock::VClock;
 
 #[derive(Debug, Clone, Default)]
-pub struct PNCounter {
-    pos: GCounter,
-    neg: GCounter,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PNCounter<T = usize> {
+    pos: GCounter<T>,
+    neg: GCounter<T>,
 }
 
 impl PNCounter {
     pub fn new() -> Self {
         Default::default()
     }
+}
 
+impl<T: CounterValue> PNCounter<T> {
     pub fn inc(&mut self, replica: ReplicaId) {
-        self.pos.inc(replica);
+        self.pos.saturating_add(replica, T::ONE);
     }
 
     pub fn dec(&mut self, replica: ReplicaId) {
-        self.neg.inc(replica);
+        self.neg.saturating_add(replica, T::ONE);
+    }
+
+    pub fn add(&mut self, replica: ReplicaId, n: T) -> Result<(), Overflow> {
+        self.pos.add(replica, n)
+    }
+
+    pub fn sub(&mut self, replica: ReplicaId, n: T) -> Result<(), Overflow> {
+        self.neg.add(replica, n)
+    }
+
+    /// Returns the contribution of the replica to the counter value
+    pub fn get(&self, replica: &ReplicaId) -> i128 {
+        Self::diff(self.pos.get(replica), self.neg.get(replica))
+    }
+
+    /// Returns the counter value, saturating at the bounds of `i128`.
+    /// Increments and decrements are summed up in `i128` rather than in `T`,
+    /// so the value is exact as long as both sums fit.
+    pub fn value(&self) -> i128 {
+        let total = |c: &GCounter<T>| {
+            c.replicas()
+                .fold(0i128, |acc, r| acc.saturating_add(version(c.get(r))))
+        };
+        total(&self.pos).saturating_sub(total(&self.neg))
+    }
+
+    pub fn checked_value(&self) -> Result<i128, Overflow> {
+        let total = |c: &GCounter<T>| {
+            c.replicas().try_fold(0i128, |acc, r| {
+                let v = c.get(r).try_into().map_err(|_| Overflow)?;
+                acc.checked_add(v).ok_or(Overflow)
+            })
+        };
+        i128::checked_sub(total(&self.pos)?, total(&self.neg)?).ok_or(Overflow)
+    }
+
+    /// Returns the version vector of the counter, the version of a replica
+    /// is the sum of its increments and decrements, which grows on every update
+    pub fn clock(&self) -> VClock {
+        self.replicas()
+            .map(|replica| (*replica, clamp(self.version(replica))))
+            .collect()
+    }
+
+    pub(super) fn version(&self, replica: &ReplicaId) -> i128 {
+        version(self.pos.get(replica)).saturating_add(version(self.neg.get(replica)))
+    }
+
+    pub(super) fn replicas(&self) -> impl Iterator<Item = &ReplicaId> {
+        self.pos.replicas().chain(self.neg.replicas())
+    }
+
+    /// Returns the contributions of the replicas matching the predicate
+    pub(super) fn filter(&self, f: impl Fn(&ReplicaId) -> bool) -> Self {
+        Self {
+            pos: self.pos.filter(&f),
+            neg: self.neg.filter(&f),
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.pos.is_empty() && self.neg.is_empty()
     }
 
-    pub fn value(&self) -> i64 {
-        self.pos.value() as i64 - self.neg.value() as i64
+    fn diff(pos: T, neg: T) -> i128 {
+        let pos = pos.try_into().unwrap_or(i128::MAX);
+        let neg = neg.try_into().unwrap_or(i128::MAX);
+        pos.saturating_sub(neg)
     }
 }
 
-impl Convergent for PNCounter {
+impl<T: CounterValue> Convergent for PNCounter<T> {
     type Delta = Self;
 
     fn merge(&mut self, other: Self) {
@@ -40,3 +109,95 @@ impl Convergent for PNCounter {
         Some(self.clone())
     }
 }
+
+impl<T: CounterValue> DeltaSince for PNCounter<T> {
+    /// Returns the contributions of the replicas whose versions are greater
+    /// than their clock entries, compared in the clamped range of the clock
+    fn delta_since(&self, clock: &VClock) -> Option<Self::Delta> {
+        let delta = self.filter(|replica| clamp(self.version(replica)) > clock.get(replica));
+        (!delta.is_empty()).then_some(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    #[test]
+    fn add_and_sub() {
+        let mut counter = PNCounter::new();
+        counter.add(REPLICA_1, 10).unwrap();
+        counter.sub(REPLICA_2, 15).unwrap();
+        counter.inc(REPLICA_2);
+        counter.dec(REPLICA_1);
+
+        assert_eq!(counter.get(&REPLICA_1), 9);
+        assert_eq!(counter.get(&REPLICA_2), -14);
+        assert_eq!(counter.value(), -5);
+    }
+
+    #[test]
+    fn merge() {
+        let mut counter1 = PNCounter::<u64>::default();
+        let mut counter2 = PNCounter::<u64>::default();
+
+        counter1.add(REPLICA_1, 7).unwrap();
+        counter2.sub(REPLICA_2, 3).unwrap();
+
+        counter1.merge(counter2.clone());
+        counter2.merge(counter1.clone());
+
+        assert_eq!(counter1.value(), 4);
+        assert_eq!(counter2.value(), 4);
+    }
+
+    #[test]
+    fn wide_values() {
+        let mut counter = PNCounter::<u128>::default();
+        counter.add(REPLICA_1, u128::MAX).unwrap();
+
+        // doesn't fit into i128
+        assert_eq!(counter.checked_value(), Err(Overflow));
+        assert_eq!(counter.value(), i128::MAX);
+
+        counter.sub(REPLICA_2, u128::MAX).unwrap();
+        assert_eq!(counter.value(), 0);
+        assert_eq!(counter.add(REPLICA_1, 1), Err(Overflow));
+    }
+
+    #[test]
+    fn value_beyond_counter_width() {
+        let mut counter = PNCounter::<u32>::default();
+        counter.add(REPLICA_1, u32::MAX).unwrap();
+        counter.add(REPLICA_2, u32::MAX).unwrap();
+        counter.sub(REPLICA_1, u32::MAX).unwrap();
+
+        assert_eq!(counter.value(), u32::MAX as i128);
+        assert_eq!(counter.checked_value(), Ok(u32::MAX as i128));
+    }
+
+    #[test]
+    fn delta_since_clock() {
+        let mut counter1 = PNCounter::new();
+        counter1.add(REPLICA_1, 5).unwrap();
+        counter1.dec(REPLICA_2);
+
+        let mut counter2 = PNCounter::new();
+        counter2.merge(counter1.clone());
+        let clock = counter2.clock();
+        assert!(counter1.delta_since(&clock).is_none());
+
+        // a decrement moves the version as much as an increment
+        counter1.dec(REPLICA_1);
+        let delta = counter1.delta_since(&clock).unwrap();
+        assert_eq!(delta.get(&REPLICA_1), 4);
+        assert_eq!(delta.get(&REPLICA_2), 0);
+
+        counter2.merge_delta(delta);
+        assert_eq!(counter2.value(), 3);
+        assert_eq!(counter2.clock(), counter1.clock());
+    }
+}
@@ -0,0 +1,264 @@
+use super::causal::{Causal, DotFun, DotStore};
+use super::{Convergent, DeltaSince, Overflow, ReplicaId};
+use crate::vclock::VClock;
+
+type CounterKernel = Causal<DotFun<(usize, usize)>>;
+
+/// Positive-negative counter which can be reset to zero. Every replica keeps
+/// its contribution under a single dot, which it replaces on each update,
+/// so the state grows with the number of replicas only. A reset removes
+/// the dots it has observed, a replica updating concurrently keeps its
+/// whole contribution as it lives under a dot the reset hasn't seen.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResettableCounter {
+    state: CounterKernel,
+    delta: Option<CounterKernel>,
+}
+
+impl ResettableCounter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn value(&self) -> i128 {
+        self.state
+            .store
+            .0
+            .values()
+            .fold(0, |acc, (pos, neg)| acc + *pos as i128 - *neg as i128)
+    }
+
+    pub fn inc(&mut self, replica: ReplicaId) -> Result<(), Overflow> {
+        self.update(replica, 1, 0)
+    }
+
+    pub fn dec(&mut self, replica: ReplicaId) -> Result<(), Overflow> {
+        self.update(replica, 0, 1)
+    }
+
+    pub fn add(&mut self, replica: ReplicaId, n: usize) -> Result<(), Overflow> {
+        self.update(replica, n, 0)
+    }
+
+    pub fn sub(&mut self, replica: ReplicaId, n: usize) -> Result<(), Overflow> {
+        self.update(replica, 0, n)
+    }
+
+    /// Returns the version vector of the counter
+    pub fn clock(&self) -> VClock {
+        self.state.context.clock()
+    }
+
+    /// Removes all observed contributions. The reset is recorded under a
+    /// dot of the replica, so replicas catching up from a clock learn of it.
+    pub fn reset(&mut self, replica: ReplicaId) -> Result<(), Overflow> {
+        if self.state.store.is_empty() {
+            return Ok(());
+        }
+        let dot = self
+            .state
+            .context
+            .checked_next_dot(replica)
+            .ok_or(Overflow)?;
+
+        let delta = self.delta.get_or_insert_default();
+        for dot in self.state.store.dots() {
+            delta.store.0.remove(&dot);
+            delta.context.add(dot);
+        }
+        delta.context.add(dot);
+        self.state.store.0.clear();
+        Ok(())
+    }
+
+    fn update(&mut self, replica: ReplicaId, pos: usize, neg: usize) -> Result<(), Overflow> {
+        let old = self.state.store.dots().find(|dot| dot.0 == replica);
+        let (p, n) = old.map_or((0, 0), |dot| self.state.store.0[&dot]);
+        let value = (
+            p.checked_add(pos).ok_or(Overflow)?,
+            n.checked_add(neg).ok_or(Overflow)?,
+        );
+        let dot = self
+            .state
+            .context
+            .checked_next_dot(replica)
+            .ok_or(Overflow)?;
+
+        let delta = self.delta.get_or_insert_default();
+        if let Some(old) = old {
+            self.state.store.0.remove(&old);
+            delta.store.0.remove(&old);
+            delta.context.add(old);
+        }
+        self.state.store.0.insert(dot, value);
+        delta.store.0.insert(dot, value);
+        delta.context.add(dot);
+        Ok(())
+    }
+}
+
+impl Convergent for ResettableCounter {
+    type Delta = CounterKernel;
+
+    fn merge(&mut self, other: Self) {
+        if let Some(delta) = other.delta {
+            let d = self.delta.get_or_insert_default();
+            d.join(delta);
+        }
+
+        self.state.join(other.state);
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) {
+        self.state.join(delta.clone());
+        self.delta.get_or_insert_default().join(delta);
+    }
+
+    fn take_delta(&mut self) -> Option<Self::Delta> {
+        self.delta.take()
+    }
+
+    fn removal_delta(&self) -> Option<Self::Delta> {
+        Some(self.state.removal())
+    }
+}
+
+impl DeltaSince for ResettableCounter {
+    /// Returns the contributions written under dots not covered
+    /// by the clock, along with the ones reset since
+    fn delta_since(&self, clock: &VClock) -> Option<Self::Delta> {
+        self.state.since(clock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "maps")]
+    use super::super::AWORMap;
+    use super::*;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    #[test]
+    fn inc_dec_reset() {
+        let mut c1 = ResettableCounter::new();
+        c1.inc(REPLICA_1).unwrap();
+        c1.add(REPLICA_1, 5).unwrap();
+        c1.dec(REPLICA_2).unwrap();
+        assert_eq!(c1.value(), 5);
+
+        c1.reset(REPLICA_1).unwrap();
+        assert_eq!(c1.value(), 0);
+
+        c1.inc(REPLICA_1).unwrap();
+        assert_eq!(c1.value(), 1);
+    }
+
+    #[test]
+    fn dot_per_replica() {
+        let mut c1 = ResettableCounter::new();
+        c1.inc(REPLICA_1).unwrap();
+        c1.inc(REPLICA_1).unwrap();
+        c1.sub(REPLICA_1, 3).unwrap();
+        c1.inc(REPLICA_2).unwrap();
+
+        assert_eq!(c1.state.store.0.len(), 2);
+        assert_eq!(c1.value(), 0);
+
+        // the delta replaces the dot on the receiver as well
+        let mut c2 = ResettableCounter::new();
+        c2.merge_delta(c1.take_delta().unwrap());
+        c1.add(REPLICA_1, 5).unwrap();
+        c2.merge_delta(c1.take_delta().unwrap());
+        assert_eq!(c2.state.store.0.len(), 2);
+        assert_eq!(c2.value(), 5);
+    }
+
+    #[test]
+    fn update_overflow() {
+        let mut c1 = ResettableCounter::new();
+        c1.add(REPLICA_1, usize::MAX).unwrap();
+        assert_eq!(c1.inc(REPLICA_1), Err(Overflow));
+        assert_eq!(c1.value(), usize::MAX as i128);
+    }
+
+    #[test]
+    fn reset_keeps_concurrent_updates() {
+        let mut c1 = ResettableCounter::new();
+        let mut c2 = ResettableCounter::new();
+
+        c1.add(REPLICA_1, 3).unwrap();
+        c2.merge(c1.clone());
+
+        // c2 resets what it has seen while c1 keeps incrementing
+        c2.reset(REPLICA_2).unwrap();
+        c1.inc(REPLICA_1).unwrap();
+        c2.add(REPLICA_2, 10).unwrap();
+
+        c1.merge(c2.clone());
+        c2.merge(c1.clone());
+
+        // c1's contribution was updated under a dot the reset hasn't seen
+        assert_eq!(c1.value(), 14);
+        assert_eq!(c2.value(), 14);
+
+        // a reset which has seen every contribution clears the counter
+        c1.reset(REPLICA_1).unwrap();
+        c2.merge_delta(c1.take_delta().unwrap());
+        assert_eq!(c2.value(), 0);
+    }
+
+    #[test]
+    fn reset_over_delta_sync() {
+        let mut c1 = ResettableCounter::new();
+        let mut c2 = ResettableCounter::new();
+
+        c1.add(REPLICA_1, 3).unwrap();
+        c2.merge_delta(c1.take_delta().unwrap());
+        assert_eq!(c2.value(), 3);
+
+        c2.reset(REPLICA_2).unwrap();
+        c1.inc(REPLICA_1).unwrap();
+
+        let d1 = c1.take_delta().unwrap();
+        let d2 = c2.take_delta().unwrap();
+        c1.merge_delta(d2);
+        c2.merge_delta(d1);
+
+        assert_eq!(c1.value(), 4);
+        assert_eq!(c2.value(), 4);
+    }
+
+    #[test]
+    #[cfg(feature = "maps")]
+    fn nested_in_map() {
+        let mut m1 = AWORMap::new(REPLICA_1);
+        m1.insert("hits".to_owned(), ResettableCounter::new());
+        m1.get_mut("hits").unwrap().add(REPLICA_1, 2).unwrap();
+
+        let mut m2: AWORMap<String, ResettableCounter> = AWORMap::new(REPLICA_2);
+        m2.merge_delta(m1.take_delta().unwrap());
+        assert_eq!(m2.get("hits").unwrap().value(), 2);
+
+        m2.get_mut("hits").unwrap().reset(REPLICA_2).unwrap();
+        m1.merge_delta(m2.take_delta().unwrap());
+        assert_eq!(m1.get("hits").unwrap().value(), 0);
+    }
+
+    #[test]
+    fn delta_since_after_reset() {
+        let mut c1 = ResettableCounter::new();
+        let mut c2 = ResettableCounter::new();
+        c1.add(REPLICA_1, 3).unwrap();
+        c2.merge(c1.clone());
+
+        c1.reset(REPLICA_1).unwrap();
+        c2.merge_delta(c1.delta_since(&c2.clock()).unwrap());
+        assert_eq!(c2.value(), 0);
+
+        assert!(c1.delta_since(&c2.clock()).is_none());
+        assert!(c2.delta_since(&c1.clock()).is_none());
+    }
+}
@@ -0,0 +1,246 @@
+use super::{Convergent, DeltaSince, FromReplica, ReplicaId};
+use crate::vclock::VClock;
+use core::cmp::Reverse;
+
+/// Register which converges to the greatest value ever assigned. Every
+/// increase is counted in the version vector of the replica making it,
+/// so a replica whose clock covers it has seen the value or a greater one.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaxRegister<T> {
+    replica_id: ReplicaId,
+    value: Option<T>,
+    clock: VClock,
+    delta: Option<T>,
+}
+
+impl<T: Ord + Clone> MaxRegister<T> {
+    pub fn new(replica_id: ReplicaId) -> Self {
+        Self {
+            replica_id,
+            value: None,
+            clock: VClock::new(),
+            delta: None,
+        }
+    }
+
+    pub fn value(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    /// Assigns the value if it is greater than the current one
+    pub fn set(&mut self, value: T) {
+        if self.value.as_ref().is_none_or(|v| *v < value) {
+            self.clock.inc(self.replica_id);
+            self.delta = Some(value.clone());
+            self.value = Some(value);
+        }
+    }
+
+    /// Returns the version vector of the increases the register has seen
+    pub fn clock(&self) -> VClock {
+        self.clock.clone()
+    }
+}
+
+/// Registers are equal when they hold the same value
+impl<T: PartialEq> PartialEq for MaxRegister<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: Eq> Eq for MaxRegister<T> {}
+
+impl<T: Ord + Clone> FromReplica for MaxRegister<T> {
+    fn from_replica(replica_id: ReplicaId) -> Self {
+        Self::new(replica_id)
+    }
+}
+
+impl<T: Ord + Clone> Convergent for MaxRegister<T> {
+    type Delta = T;
+
+    fn merge(&mut self, other: Self) {
+        if let Some(value) = other.value {
+            if self.value.as_ref().is_none_or(|v| *v < value) {
+                self.value = Some(value);
+            }
+        }
+        self.clock.merge(&other.clock);
+    }
+
+    /// Deltas carry no version, so an increase coming from a delta
+    /// is counted as an increase made by this replica
+    fn merge_delta(&mut self, delta: Self::Delta) {
+        self.set(delta);
+    }
+
+    fn take_delta(&mut self) -> Option<Self::Delta> {
+        self.delta.take()
+    }
+}
+
+impl<T: Ord + Clone> DeltaSince for MaxRegister<T> {
+    /// Returns the value unless the clock covers every increase of it
+    fn delta_since(&self, clock: &VClock) -> Option<Self::Delta> {
+        if clock.descends(&self.clock) {
+            None
+        } else {
+            self.value.clone()
+        }
+    }
+}
+
+/// Register which converges to the smallest value ever assigned
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MinRegister<T>(MaxRegister<Reverse<T>>);
+
+impl<T: Ord + Clone> MinRegister<T> {
+    pub fn new(replica_id: ReplicaId) -> Self {
+        Self(MaxRegister::new(replica_id))
+    }
+
+    pub fn value(&self) -> Option<&T> {
+        self.0.value().map(|v| &v.0)
+    }
+
+    /// Assigns the value if it is less than the current one
+    pub fn set(&mut self, value: T) {
+        self.0.set(Reverse(value));
+    }
+
+    /// Returns the version vector of the decreases the register has seen
+    pub fn clock(&self) -> VClock {
+        self.0.clock()
+    }
+}
+
+impl<T: Ord + Clone> FromReplica for MinRegister<T> {
+    fn from_replica(replica_id: ReplicaId) -> Self {
+        Self::new(replica_id)
+    }
+}
+
+impl<T: Ord + Clone> Convergent for MinRegister<T> {
+    type Delta = T;
+
+    fn merge(&mut self, other: Self) {
+        self.0.merge(other.0);
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) {
+        self.0.merge_delta(Reverse(delta));
+    }
+
+    fn take_delta(&mut self) -> Option<Self::Delta> {
+        self.0.take_delta().map(|v| v.0)
+    }
+}
+
+impl<T: Ord + Clone> DeltaSince for MinRegister<T> {
+    /// Returns the value unless the clock covers every decrease of it
+    fn delta_since(&self, clock: &VClock) -> Option<Self::Delta> {
+        self.0.delta_since(clock).map(|v| v.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    #[test]
+    fn max_register() {
+        let mut r1 = MaxRegister::new(REPLICA_1);
+        let mut r2 = MaxRegister::new(REPLICA_2);
+        assert_eq!(r1.value(), None);
+
+        r1.set(5);
+        r1.set(3);
+        assert_eq!(r1.value(), Some(&5));
+
+        r2.set(7);
+        r1.merge(r2.clone());
+        r2.merge(r1.clone());
+        assert_eq!(r1.value(), Some(&7));
+        assert_eq!(r2.value(), Some(&7));
+    }
+
+    #[test]
+    fn min_register() {
+        let mut r1 = MinRegister::new(REPLICA_1);
+        let mut r2 = MinRegister::new(REPLICA_2);
+
+        r1.set(5);
+        r1.set(8);
+        assert_eq!(r1.value(), Some(&5));
+
+        r2.set(2);
+        r1.merge(r2.clone());
+        assert_eq!(r1.value(), Some(&2));
+    }
+
+    #[test]
+    fn delta_sync() {
+        let mut r1 = MaxRegister::new(REPLICA_1);
+        let mut r2 = MaxRegister::new(REPLICA_2);
+
+        r1.set(1);
+        r1.set(4);
+        r2.set(3);
+
+        // delta only carries the latest increase
+        assert_eq!(r1.take_delta(), Some(4));
+        assert_eq!(r1.take_delta(), None);
+
+        r2.take_delta();
+        r2.merge_delta(4);
+        assert_eq!(r2.value(), Some(&4));
+
+        // a smaller value is not an update
+        r2.merge_delta(2);
+        assert_eq!(r2.take_delta(), Some(4));
+
+        let mut m1 = MinRegister::new(REPLICA_1);
+        m1.set(9);
+        m1.set(10);
+        assert_eq!(m1.take_delta(), Some(9));
+    }
+
+    #[test]
+    fn delta_since_clock() {
+        let mut r1 = MaxRegister::new(REPLICA_1);
+        let mut r2 = MaxRegister::new(REPLICA_2);
+        assert!(r1.delta_since(&VClock::new()).is_none());
+
+        r1.set(5);
+        r2.merge(r1.clone());
+        assert!(r1.delta_since(&r2.clock()).is_none());
+
+        // concurrent increases are missing on both sides
+        r1.set(7);
+        r2.set(6);
+        assert_eq!(r1.delta_since(&r2.clock()), Some(7));
+        assert_eq!(r2.delta_since(&r1.clock()), Some(6));
+
+        r2.merge(r1.clone());
+        assert!(r1.delta_since(&r2.clock()).is_none());
+        assert_eq!(r2.value(), Some(&7));
+    }
+
+    #[test]
+    fn eq_ignores_pending_delta() {
+        let mut r1 = MaxRegister::new(REPLICA_1);
+        let mut r2 = MaxRegister::new(REPLICA_2);
+        r1.set(3);
+        r2.merge(r1.clone());
+        assert_eq!(r1, r2);
+
+        r1.take_delta();
+        assert_eq!(r1, r2);
+    }
+}
@@ -0,0 +1,165 @@
+use super::{Convergent, GSet};
+use crate::collections::HashSet;
+use core::borrow::Borrow;
+use core::hash::Hash;
+
+/// Set where elements can be removed once and never added back
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "T: serde::Serialize + Eq + Hash",
+        deserialize = "T: serde::Deserialize<'de> + Eq + Hash"
+    ))
+)]
+pub struct TwoPhaseSet<T> {
+    added: GSet<T>,
+    removed: GSet<T>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "T: serde::Serialize + Eq + Hash",
+        deserialize = "T: serde::Deserialize<'de> + Eq + Hash"
+    ))
+)]
+pub struct TwoPhaseSetDelta<T> {
+    added: Option<HashSet<T>>,
+    removed: Option<HashSet<T>>,
+}
+
+impl<T> Default for TwoPhaseSet<T> {
+    fn default() -> Self {
+        Self {
+            added: GSet::default(),
+            removed: GSet::default(),
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> TwoPhaseSet<T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.added.contains(value) && !self.removed.contains(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.added.iter().filter(|v| !self.removed.contains(*v))
+    }
+
+    pub fn add(&mut self, value: T) {
+        self.added.add(value);
+    }
+
+    /// Removes the element for good, has no effect if it was never added
+    pub fn remove<Q>(&mut self, value: &Q)
+    where
+        T: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if let Some(value) = self.added.get(value).cloned() {
+            self.removed.add(value);
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> Convergent for TwoPhaseSet<T> {
+    type Delta = TwoPhaseSetDelta<T>;
+
+    fn merge(&mut self, other: Self) {
+        self.added.merge(other.added);
+        self.removed.merge(other.removed);
+    }
+
+    fn merge_delta(&mut self, delta: Self::Delta) {
+        if let Some(added) = delta.added {
+            self.added.merge_delta(added);
+        }
+        if let Some(removed) = delta.removed {
+            self.removed.merge_delta(removed);
+        }
+    }
+
+    fn take_delta(&mut self) -> Option<Self::Delta> {
+        let added = self.added.take_delta();
+        let removed = self.removed.take_delta();
+
+        if added.is_none() && removed.is_none() {
+            None
+        } else {
+            Some(TwoPhaseSetDelta { added, removed })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remove_is_permanent() {
+        let mut s1 = TwoPhaseSet::new();
+
+        s1.add("foo");
+        s1.remove("foo");
+        assert!(!s1.contains("foo"));
+
+        s1.add("foo");
+        assert!(!s1.contains("foo"));
+    }
+
+    #[test]
+    fn remove_unknown_element() {
+        let mut s1 = TwoPhaseSet::new();
+
+        s1.remove("foo");
+        s1.add("foo");
+        assert!(s1.contains("foo"));
+    }
+
+    #[test]
+    fn merge_removal_wins() {
+        let mut s1 = TwoPhaseSet::new();
+        let mut s2 = TwoPhaseSet::new();
+
+        s1.add("foo");
+        s2.merge(s1.clone());
+        s2.remove("foo");
+        s1.add("bar");
+
+        s1.merge(s2.clone());
+        s2.merge(s1.clone());
+
+        assert_eq!(s1.iter().collect::<Vec<_>>(), vec![&"bar"]);
+        assert_eq!(s2.iter().collect::<Vec<_>>(), vec![&"bar"]);
+    }
+
+    #[test]
+    fn delta_sync() {
+        let mut s1 = TwoPhaseSet::new();
+        let mut s2 = TwoPhaseSet::new();
+
+        s1.add("foo");
+        s2.merge_delta(s1.take_delta().unwrap());
+        assert!(s2.contains("foo"));
+
+        s1.remove("foo");
+        let delta = s1.take_delta().unwrap();
+        assert!(delta.added.is_none());
+
+        s2.merge_delta(delta);
+        assert!(!s2.contains("foo"));
+        assert!(s1.take_delta().is_none());
+    }
+}
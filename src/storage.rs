@@ -0,0 +1,571 @@
+//! Durable replicas: a snapshot of the full state plus an append-only log
+//! of the deltas applied since, replayed when the store is opened.
+//!
+//! Records in both files are a little-endian `u32` length, the CRC-32 of
+//! the length and the CRC-32 of the length and payload, followed by the
+//! bincode payload. A torn record at the end of the log, left by a crash
+//! in the middle of an append, is dropped on recovery, while a corrupt
+//! record followed by others fails opening the store.
+
+#[cfg(feature = "maps")]
+mod values;
+
+#[cfg(feature = "maps")]
+pub use values::{FileAWORMap, FileValueStore};
+
+use crate::Convergent;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT: &str = "snapshot";
+const SNAPSHOT_TMP: &str = "snapshot.tmp";
+const LOG: &str = "wal";
+
+const HEADER_LEN: usize = 12;
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    Encoding(bincode::Error),
+    /// The snapshot is cut short or fails its checksum
+    CorruptSnapshot,
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "storage i/o error: {}", e),
+            Self::Encoding(e) => write!(f, "storage encoding error: {}", e),
+            Self::CorruptSnapshot => write!(f, "corrupt snapshot"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Encoding(e) => Some(e),
+            Self::CorruptSnapshot => None,
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<bincode::Error> for StorageError {
+    fn from(e: bincode::Error) -> Self {
+        Self::Encoding(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StoreOptions {
+    /// Number of logged deltas after which the log is folded into a new snapshot
+    pub compact_after: usize,
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        Self {
+            compact_after: 1000,
+        }
+    }
+}
+
+/// Replica state persisted in a directory of the local filesystem
+#[derive(Debug)]
+pub struct Store<T: Convergent> {
+    dir: PathBuf,
+    log: File,
+    // length of the log up to the last good record
+    end: u64,
+    logged: usize,
+    // deltas applied to the state whose append failed,
+    // they are persisted by the next snapshot
+    unlogged: Vec<T::Delta>,
+    options: StoreOptions,
+    state: T,
+}
+
+impl<T> Store<T>
+where
+    T: Convergent + Serialize + DeserializeOwned,
+    T::Delta: Serialize + DeserializeOwned,
+{
+    /// Opens the store in `dir`, starting from `init` if nothing was persisted yet
+    pub fn open(dir: impl AsRef<Path>, init: T) -> Result<Self, StorageError> {
+        Self::open_with(dir, init, StoreOptions::default())
+    }
+
+    pub fn open_with(
+        dir: impl AsRef<Path>,
+        init: T,
+        options: StoreOptions,
+    ) -> Result<Self, StorageError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        // left over from a crash before the snapshot was renamed in place
+        match fs::remove_file(dir.join(SNAPSHOT_TMP)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        let mut state = match File::open(dir.join(SNAPSHOT)) {
+            Ok(file) => match read_record(&mut BufReader::new(file))? {
+                Some(bytes) => bincode::deserialize(&bytes)?,
+                None => return Err(StorageError::CorruptSnapshot),
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => init,
+            Err(e) => return Err(e.into()),
+        };
+
+        let log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(LOG))?;
+
+        let mut reader = BufReader::new(&log);
+        let mut valid = 0;
+        let mut logged = 0;
+        while let Some(bytes) = read_record(&mut reader)? {
+            state.merge_delta(bincode::deserialize(&bytes)?);
+            valid += (HEADER_LEN + bytes.len()) as u64;
+            logged += 1;
+        }
+        // the replayed deltas are logged already
+        state.take_delta();
+
+        // drop the torn tail so that new records follow the last good one
+        if log.metadata()?.len() > valid {
+            log.set_len(valid)?;
+            log.sync_all()?;
+        }
+        sync_dir(&dir)?;
+
+        Ok(Self {
+            dir,
+            log,
+            end: valid,
+            logged,
+            unlogged: Vec::new(),
+            options,
+            state,
+        })
+    }
+
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    pub fn into_inner(self) -> T {
+        self.state
+    }
+
+    /// Applies a local update and logs the delta it produced,
+    /// which is returned so that it can be shipped to other replicas.
+    /// The update is applied even if logging fails, its delta is then
+    /// kept and persisted by the next snapshot, taken by the next write.
+    pub fn update<F: FnOnce(&mut T)>(&mut self, f: F) -> Result<Option<T::Delta>, StorageError> {
+        self.prepare()?;
+        f(&mut self.state);
+        let Some(delta) = self.state.take_delta() else {
+            return Ok(None);
+        };
+        match self.append(&delta) {
+            Ok(()) => Ok(Some(delta)),
+            Err(e) => {
+                self.unlogged.push(delta);
+                Err(e)
+            }
+        }
+    }
+
+    /// Logs a delta received from another replica, then merges it
+    pub fn merge_delta(&mut self, delta: T::Delta) -> Result<(), StorageError> {
+        self.prepare()?;
+        self.append(&delta)?;
+        self.state.merge_delta(delta);
+        // what the state forwards of the delta is logged already
+        self.state.take_delta();
+        Ok(())
+    }
+
+    /// Merges the full state of another replica and snapshots the result
+    pub fn merge(&mut self, other: T) -> Result<(), StorageError> {
+        self.state.merge(other);
+        self.state.take_delta();
+        self.snapshot()
+    }
+
+    /// Writes the current state as a new snapshot and truncates the log
+    pub fn snapshot(&mut self) -> Result<(), StorageError> {
+        let bytes = bincode::serialize(&self.state)?;
+        let tmp = self.dir.join(SNAPSHOT_TMP);
+        let mut file = File::create(&tmp)?;
+        write_record(&mut file, &bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.dir.join(SNAPSHOT))?;
+        sync_dir(&self.dir)?;
+
+        // crashing here replays the old log onto the new snapshot,
+        // which is harmless as merging a delta twice is a no-op
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        self.end = 0;
+        self.logged = 0;
+        self.unlogged.clear();
+        Ok(())
+    }
+
+    /// Persists the deltas whose append failed and folds
+    /// the log into a snapshot when it's due
+    fn prepare(&mut self) -> Result<(), StorageError> {
+        if !self.unlogged.is_empty() || self.logged >= self.options.compact_after {
+            self.snapshot()?;
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, delta: &T::Delta) -> Result<(), StorageError> {
+        let bytes = bincode::serialize(delta)?;
+        let written = write_record(&mut self.log, &bytes).and_then(|_| self.log.sync_data());
+        if let Err(e) = written {
+            // drop what made it to the file so that later records
+            // don't follow a torn one
+            let _ = self.log.set_len(self.end);
+            return Err(e.into());
+        }
+        self.end += (HEADER_LEN + bytes.len()) as u64;
+        self.logged += 1;
+        Ok(())
+    }
+}
+
+fn write_record<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    let len = (bytes.len() as u32).to_le_bytes();
+    let len_crc = crc32(&len);
+    let mut record = Vec::with_capacity(HEADER_LEN + bytes.len());
+    record.extend_from_slice(&len);
+    record.extend_from_slice(&len_crc.to_le_bytes());
+    record.extend_from_slice(&crc32_append(len_crc, bytes).to_le_bytes());
+    record.extend_from_slice(bytes);
+    w.write_all(&record)
+}
+
+/// Reads the next record, or `None` at the end of input or when the last
+/// record is cut short or fails its checksum, as left by a torn write.
+/// A record failing its checksum with more data after it is an error,
+/// as is a length failing its checksum, which can't tell where the
+/// record ends.
+fn read_record<R: BufRead>(r: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0; HEADER_LEN];
+    if !read_full(r, &mut header)? {
+        return Ok(None);
+    }
+
+    let len_crc = crc32(&header[..4]);
+    if len_crc != u32::from_le_bytes(header[4..8].try_into().unwrap()) {
+        if r.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "record length fails its checksum",
+        ));
+    }
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[8..].try_into().unwrap());
+
+    let mut bytes = Vec::new();
+    r.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Ok(None);
+    }
+    if crc32_append(len_crc, &bytes) != crc {
+        if r.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "record fails its checksum before the end of the file",
+        ));
+    }
+    Ok(Some(bytes))
+}
+
+fn read_full<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    match r.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    crc32_append(0, bytes)
+}
+
+/// Extends the checksum of some bytes to the checksum of them followed by `bytes`
+fn crc32_append(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Makes renames and newly created files in `dir` durable
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "counters", feature = "sets"))]
+mod tests {
+    use super::*;
+    use crate::state_crdt::{AWORSet, PNCounter};
+    use crate::ReplicaId;
+    use std::mem;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("crdt-storage-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn recover_by_replay() {
+        let dir = test_dir("replay");
+
+        let mut store = Store::open(&dir, AWORSet::new(REPLICA_1)).unwrap();
+        store.update(|s| s.add("foo".to_owned())).unwrap();
+        store.update(|s| s.add("bar".to_owned())).unwrap();
+        store.update(|s| s.remove("foo")).unwrap();
+        drop(store);
+
+        let store = Store::open(&dir, AWORSet::<String>::new(REPLICA_1)).unwrap();
+        assert!(!store.state().contains("foo"));
+        assert!(store.state().contains("bar"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remote_deltas_are_logged() {
+        let dir = test_dir("remote");
+
+        let mut remote = AWORSet::new(REPLICA_2);
+        remote.add("baz".to_owned());
+
+        let mut store = Store::open(&dir, AWORSet::new(REPLICA_1)).unwrap();
+        store.merge_delta(remote.take_delta().unwrap()).unwrap();
+        drop(store);
+
+        let store = Store::open(&dir, AWORSet::<String>::new(REPLICA_1)).unwrap();
+        assert!(store.state().contains("baz"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn torn_tail_is_dropped() {
+        let dir = test_dir("torn");
+
+        let mut store = Store::open(&dir, PNCounter::new()).unwrap();
+        store.update(|c| c.inc(REPLICA_1)).unwrap();
+        store.update(|c| c.inc(REPLICA_1)).unwrap();
+        drop(store);
+
+        // half written record after the good ones
+        let mut log = OpenOptions::new().append(true).open(dir.join(LOG)).unwrap();
+        log.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+        drop(log);
+
+        let mut store = Store::open(&dir, PNCounter::new()).unwrap();
+        assert_eq!(store.state().value(), 2);
+
+        // appends go after the last good record
+        store.update(|c| c.dec(REPLICA_1)).unwrap();
+        drop(store);
+
+        let store = Store::open(&dir, PNCounter::new()).unwrap();
+        assert_eq!(store.state().value(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_record_before_tail() {
+        let dir = test_dir("corrupt_record");
+
+        let mut store = Store::open(&dir, PNCounter::new()).unwrap();
+        store.update(|c| c.inc(REPLICA_1)).unwrap();
+        store.update(|c| c.inc(REPLICA_1)).unwrap();
+        drop(store);
+
+        // flip a payload byte of the first record
+        let mut bytes = fs::read(dir.join(LOG)).unwrap();
+        bytes[HEADER_LEN] ^= 0xff;
+        fs::write(dir.join(LOG), &bytes).unwrap();
+
+        let err = Store::open(&dir, PNCounter::new()).unwrap_err();
+        assert!(matches!(err, StorageError::Io(e) if e.kind() == ErrorKind::InvalidData));
+
+        // the log is left as it was for inspection
+        assert_eq!(fs::read(dir.join(LOG)).unwrap(), bytes);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_length_before_tail() {
+        let dir = test_dir("corrupt_length");
+
+        let mut store = Store::open(&dir, PNCounter::new()).unwrap();
+        store.update(|c| c.inc(REPLICA_1)).unwrap();
+        store.update(|c| c.inc(REPLICA_1)).unwrap();
+        drop(store);
+
+        // the length of the first record points past the end of the file
+        let mut bytes = fs::read(dir.join(LOG)).unwrap();
+        bytes[1] = 0xff;
+        fs::write(dir.join(LOG), &bytes).unwrap();
+
+        let err = Store::open(&dir, PNCounter::new()).unwrap_err();
+        assert!(matches!(err, StorageError::Io(e) if e.kind() == ErrorKind::InvalidData));
+        assert_eq!(fs::read(dir.join(LOG)).unwrap(), bytes);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_append_keeps_delta() {
+        let dir = test_dir("failed_append");
+
+        let mut store = Store::open(&dir, PNCounter::new()).unwrap();
+        store.update(|c| c.inc(REPLICA_1)).unwrap();
+
+        // a read only handle fails the next append
+        let log = mem::replace(&mut store.log, File::open(dir.join(LOG)).unwrap());
+        assert!(store.update(|c| c.inc(REPLICA_1)).is_err());
+        assert_eq!(store.unlogged.len(), 1);
+
+        store.log = log;
+        store.update(|c| c.inc(REPLICA_1)).unwrap();
+        assert!(store.unlogged.is_empty());
+        drop(store);
+
+        let store = Store::open(&dir, PNCounter::new()).unwrap();
+        assert_eq!(store.state().value(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merged_deltas_are_logged_once() {
+        let dir = test_dir("logged_once");
+
+        let mut remote = AWORSet::new(REPLICA_2);
+        remote.add("baz".to_owned());
+
+        let mut store = Store::open(&dir, AWORSet::new(REPLICA_1)).unwrap();
+        store.merge_delta(remote.take_delta().unwrap()).unwrap();
+        let delta = store.update(|s| s.add("foo".to_owned())).unwrap().unwrap();
+        assert!(delta.store.0.values().all(|k| k != "baz"));
+        assert_eq!(store.logged, 2);
+        drop(store);
+
+        // replayed deltas aren't logged again either
+        let mut store = Store::open(&dir, AWORSet::<String>::new(REPLICA_1)).unwrap();
+        let delta = store.update(|s| s.add("bar".to_owned())).unwrap().unwrap();
+        assert!(delta.store.0.values().all(|k| k == "bar"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compaction() {
+        let dir = test_dir("compaction");
+        let options = StoreOptions { compact_after: 3 };
+
+        let mut store = Store::open_with(&dir, PNCounter::new(), options).unwrap();
+        for _ in 0..4 {
+            store.update(|c| c.inc(REPLICA_1)).unwrap();
+        }
+        assert!(dir.join(SNAPSHOT).exists());
+        assert_eq!(store.logged, 1);
+        drop(store);
+
+        let store = Store::open_with(&dir, PNCounter::new(), options).unwrap();
+        assert_eq!(store.state().value(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn crash_before_log_truncation() {
+        let dir = test_dir("truncation");
+
+        let mut store = Store::open(&dir, AWORSet::new(REPLICA_1)).unwrap();
+        store.update(|s| s.add("foo".to_owned())).unwrap();
+        store.update(|s| s.add("bar".to_owned())).unwrap();
+        let log = fs::read(dir.join(LOG)).unwrap();
+        store.snapshot().unwrap();
+        drop(store);
+
+        // the old log survived next to the new snapshot
+        fs::write(dir.join(LOG), log).unwrap();
+
+        let store = Store::open(&dir, AWORSet::<String>::new(REPLICA_1)).unwrap();
+        let mut keys: Vec<_> = store.state().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, ["bar", "foo"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_snapshot() {
+        let dir = test_dir("corrupt");
+
+        let mut store = Store::open(&dir, PNCounter::new()).unwrap();
+        store.update(|c| c.inc(REPLICA_1)).unwrap();
+        store.snapshot().unwrap();
+        drop(store);
+
+        let mut bytes = fs::read(dir.join(SNAPSHOT)).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        fs::write(dir.join(SNAPSHOT), bytes).unwrap();
+
+        let err = Store::open(&dir, PNCounter::new()).unwrap_err();
+        assert!(matches!(err, StorageError::CorruptSnapshot));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
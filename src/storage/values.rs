@@ -0,0 +1,578 @@
+use crate::collections::{HashMap, HashSet};
+use crate::state_crdt::{AWORMapBase, ValueStore};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Add-wins map with values paged out to a file
+pub type FileAWORMap<K, V> = AWORMapBase<K, V, FileValueStore<K, V>>;
+
+// two independent hashes of a key, which stand in for it in memory
+type KeyId = u128;
+
+struct Slot<K, V> {
+    // the key along with the value while loaded
+    entry: Option<(K, V)>,
+    // offset and length of the last copy written to the file
+    location: Option<(u64, u32)>,
+    dirty: bool,
+}
+
+/// Value storage keeping only recently changed values in memory, the rest
+/// live in a file. Once more than `capacity` values are loaded for changes,
+/// the oldest loaded ones are written back and dropped. Reads of values
+/// which are not loaded return a copy read from the file, so reads don't
+/// grow the memory used.
+///
+/// Keys are written to the file along with their values, in memory values
+/// are identified by a 128 bit hash of their key, and the keys are taken
+/// from the map's key set. The file is scratch space removed along with
+/// the store, use [`Store`](super::Store) for durability.
+pub struct FileValueStore<K, V> {
+    path: PathBuf,
+    file: RefCell<File>,
+    end: u64,
+    garbage: u64,
+    hashers: (RandomState, RandomState),
+    slots: HashMap<KeyId, Slot<K, V>>,
+    // ids of the loaded values, oldest first, along with
+    // ids of values dropped since they were loaded
+    queue: VecDeque<KeyId>,
+    loaded: usize,
+    capacity: usize,
+}
+
+/// Value of a [`FileValueStore`], either loaded or read from the file
+pub enum ValueRef<'a, V> {
+    Loaded(&'a V),
+    Read(V),
+}
+
+impl<V> Deref for ValueRef<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        match self {
+            ValueRef::Loaded(v) => v,
+            ValueRef::Read(v) => v,
+        }
+    }
+}
+
+impl<K, V> FileValueStore<K, V> {
+    /// Creates the store on a new file at `path`,
+    /// keeping up to `capacity` values in memory
+    pub fn create(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+
+        Ok(Self {
+            path,
+            file: RefCell::new(file),
+            end: 0,
+            garbage: 0,
+            hashers: (RandomState::new(), RandomState::new()),
+            slots: HashMap::new(),
+            queue: VecDeque::new(),
+            loaded: 0,
+            capacity,
+        })
+    }
+
+    /// Creates the store on a new file in the temporary directory
+    pub fn temp(capacity: usize) -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let name = format!(
+            "crdt-values-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        Self::create(std::env::temp_dir().join(name), capacity)
+    }
+
+    /// Rewrites the file without the space taken by stale copies of values
+    pub fn compact(&mut self) -> io::Result<()> {
+        let tmp = self.path.with_extension("compact");
+        let mut out = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)?;
+
+        let file = self.file.get_mut();
+        let mut end = 0;
+        for slot in self.slots.values_mut() {
+            if let Some((offset, len)) = slot.location {
+                let mut bytes = vec![0; len as usize];
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(&mut bytes)?;
+                out.write_all(&bytes)?;
+                slot.location = Some((end, len));
+                end += len as u64;
+            }
+        }
+
+        fs::rename(&tmp, &self.path)?;
+        *file = out;
+        self.end = end;
+        self.garbage = 0;
+        Ok(())
+    }
+}
+
+impl<K: Eq + Hash, V> FileValueStore<K, V> {
+    fn id(&self, key: &K) -> KeyId {
+        let (h1, h2) = &self.hashers;
+        (h1.hash_one(key) as u128) << 64 | h2.hash_one(key) as u128
+    }
+}
+
+impl<K, V> FileValueStore<K, V>
+where
+    K: Eq + Hash + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    /// Reads the value of the key, checking the key written along with it
+    fn read(&self, key: &K, location: Option<(u64, u32)>) -> io::Result<V> {
+        let (k, v) = read(&self.file, location)?;
+        check_key(key, &k)?;
+        Ok(v)
+    }
+
+    fn take(&mut self, key: &K) -> io::Result<Option<V>> {
+        let id = self.id(key);
+        let Some(slot) = self.slots.get(&id) else {
+            return Ok(None);
+        };
+        let value = match &slot.entry {
+            Some((k, _)) => check_key(key, k).map(|_| None),
+            None => self.read(key, slot.location).map(Some),
+        }?;
+
+        let slot = self.slots.remove(&id).unwrap();
+        if let Some((_, len)) = slot.location {
+            self.garbage += len as u64;
+        }
+        match slot.entry {
+            Some((_, v)) => {
+                self.loaded -= 1;
+                Ok(Some(v))
+            }
+            None => Ok(value),
+        }
+    }
+
+    /// Writes back and drops the oldest loaded values while there are too many
+    fn evict(&mut self) -> io::Result<()> {
+        while self.loaded > self.capacity {
+            let Some(id) = self.queue.pop_front() else {
+                break;
+            };
+            let Some(slot) = self.slots.get_mut(&id) else {
+                continue;
+            };
+            let Some(entry) = &slot.entry else {
+                continue;
+            };
+            if slot.dirty {
+                let location = write(self.file.get_mut(), &mut self.end, &encode(entry)?)?;
+                if let Some((_, len)) = slot.location.replace(location) {
+                    self.garbage += len as u64;
+                }
+                slot.dirty = false;
+            }
+            slot.entry = None;
+            self.loaded -= 1;
+        }
+
+        // drop the ids of values which were dropped or loaded again
+        if self.queue.len() > 2 * self.loaded + 16 {
+            let mut seen = HashSet::new();
+            let slots = &self.slots;
+            self.queue
+                .retain(|id| slots.get(id).is_some_and(|s| s.entry.is_some()) && seen.insert(*id));
+        }
+
+        self.compact_if_sparse()
+    }
+
+    fn compact_if_sparse(&mut self) -> io::Result<()> {
+        if self.garbage > self.end / 2 {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+fn check_key<K: Eq>(key: &K, found: &K) -> io::Result<()> {
+    if key == found {
+        Ok(())
+    } else {
+        Err(io::Error::new(ErrorKind::InvalidData, "key hash collision"))
+    }
+}
+
+fn read<T: DeserializeOwned>(file: &RefCell<File>, location: Option<(u64, u32)>) -> io::Result<T> {
+    let Some((offset, len)) = location else {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            "value is neither loaded nor written",
+        ));
+    };
+    let mut bytes = vec![0; len as usize];
+    let mut file = file.borrow_mut();
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes)?;
+    decode(&bytes)
+}
+
+fn write(file: &mut File, end: &mut u64, bytes: &[u8]) -> io::Result<(u64, u32)> {
+    file.seek(SeekFrom::Start(*end))?;
+    file.write_all(bytes)?;
+
+    let location = (*end, bytes.len() as u32);
+    *end += bytes.len() as u64;
+    Ok(location)
+}
+
+fn encode<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+    bincode::serialize(value).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T> {
+    bincode::deserialize(bytes).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+impl<K, V> Drop for FileValueStore<K, V> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl<K, V> fmt::Debug for FileValueStore<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileValueStore")
+            .field("path", &self.path)
+            .field("len", &self.slots.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl<K, V> ValueStore<K, V> for FileValueStore<K, V>
+where
+    K: Eq + Hash + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    type Error = io::Error;
+    type Ref<'a>
+        = ValueRef<'a, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Checks the hash of the key only, the map only asks
+    /// for keys of its key set
+    fn contains_key(&self, key: &K) -> bool {
+        self.slots.contains_key(&self.id(key))
+    }
+
+    fn keys<'a>(&'a self, map_keys: impl Iterator<Item = &'a K> + 'a) -> impl Iterator<Item = &'a K>
+    where
+        K: 'a,
+    {
+        map_keys.filter(|k| self.contains_key(k))
+    }
+
+    fn get(&self, key: &K) -> io::Result<Option<ValueRef<'_, V>>> {
+        let Some(slot) = self.slots.get(&self.id(key)) else {
+            return Ok(None);
+        };
+        match &slot.entry {
+            Some((k, v)) => check_key(key, k).map(|_| Some(ValueRef::Loaded(v))),
+            None => self
+                .read(key, slot.location)
+                .map(|v| Some(ValueRef::Read(v))),
+        }
+    }
+
+    fn get_mut(&mut self, key: &K) -> io::Result<Option<&mut V>> {
+        self.evict()?;
+
+        let id = self.id(key);
+        let Some(slot) = self.slots.get(&id) else {
+            return Ok(None);
+        };
+        let entry = match &slot.entry {
+            Some((k, _)) => check_key(key, k).map(|_| None),
+            None => read(&self.file, slot.location).map(Some),
+        }?;
+
+        let slot = self.slots.get_mut(&id).unwrap();
+        if let Some((k, v)) = entry {
+            check_key(key, &k)?;
+            slot.entry = Some((k, v));
+            self.loaded += 1;
+            self.queue.push_back(id);
+        }
+        slot.dirty = true;
+        Ok(slot.entry.as_mut().map(|(_, v)| v))
+    }
+
+    fn insert(&mut self, key: K, value: V) -> io::Result<Option<V>> {
+        self.evict()?;
+
+        let id = self.id(&key);
+        // a loaded value being replaced is queued already
+        let queued = self.slots.get(&id).is_some_and(|s| s.entry.is_some());
+        let old = self.take(&key)?;
+        let slot = Slot {
+            entry: Some((key, value)),
+            location: None,
+            dirty: true,
+        };
+        self.slots.insert(id, slot);
+        self.loaded += 1;
+        if !queued {
+            self.queue.push_back(id);
+        }
+        Ok(old)
+    }
+
+    fn remove(&mut self, key: &K) -> io::Result<Option<V>> {
+        self.take(key)
+    }
+
+    fn iter<'a>(
+        &'a self,
+        map_keys: impl Iterator<Item = &'a K> + 'a,
+    ) -> impl Iterator<Item = io::Result<(&'a K, ValueRef<'a, V>)>>
+    where
+        K: 'a,
+        V: 'a,
+    {
+        map_keys.filter_map(|k| match self.get(k) {
+            Ok(v) => v.map(|v| Ok((k, v))),
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    fn into_entries(mut self) -> impl Iterator<Item = io::Result<(K, V)>> {
+        let slots = mem::take(&mut self.slots);
+        slots.into_values().map(move |slot| match slot.entry {
+            Some(entry) => Ok(entry),
+            None => read(&self.file, slot.location),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "counters"))]
+mod tests {
+    use super::*;
+    use crate::state_crdt::{AWORMap, Convergent, GCounter, MaxRegister};
+    use crate::ReplicaId;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    fn test_store<V>(name: &str, capacity: usize) -> FileValueStore<u64, V> {
+        let path =
+            std::env::temp_dir().join(format!("crdt-values-test-{}-{}", std::process::id(), name));
+        FileValueStore::create(path, capacity).unwrap()
+    }
+
+    fn resident<K, V>(store: &FileValueStore<K, V>) -> usize {
+        store.slots.values().filter(|s| s.entry.is_some()).count()
+    }
+
+    #[test]
+    fn pages_values_out() {
+        let mut store = test_store("paging", 4);
+        for i in 0..100 {
+            store.insert(i, i * 10).unwrap();
+        }
+        assert!(resident(&store) <= 5);
+        assert!(store.end > 0);
+
+        for i in 0..100 {
+            assert_eq!(*store.get(&i).unwrap().unwrap(), i * 10);
+        }
+        assert_eq!(store.len(), 100);
+
+        // reading doesn't load values
+        assert!(resident(&store) <= 5);
+        let keys: Vec<_> = (0..100).collect();
+        let sum = store.iter(keys.iter()).map(|e| *e.unwrap().1).sum::<u64>();
+        assert_eq!(sum, 49500);
+        assert!(resident(&store) <= 5);
+    }
+
+    #[test]
+    fn changes_survive_eviction() {
+        let mut store = test_store("changes", 2);
+        for i in 0..10 {
+            store.insert(i, i).unwrap();
+        }
+        for i in 0..10 {
+            *store.get_mut(&i).unwrap().unwrap() += 1;
+        }
+        assert_eq!(store.remove(&3).unwrap(), Some(4));
+        assert_eq!(store.insert(5, 0).unwrap(), Some(6));
+
+        let mut all: Vec<_> = store.into_entries().map(Result::unwrap).collect();
+        all.sort();
+        assert_eq!(
+            all,
+            [
+                (0, 1),
+                (1, 2),
+                (2, 3),
+                (4, 5),
+                (5, 0),
+                (6, 7),
+                (7, 8),
+                (8, 9),
+                (9, 10)
+            ]
+        );
+    }
+
+    #[test]
+    fn evicts_oldest_values_only() {
+        let mut store = test_store("eviction", 4);
+        for i in 0..4 {
+            store.insert(i, i).unwrap();
+        }
+        for _ in 0..10 {
+            for i in 0..4 {
+                *store.get_mut(&i).unwrap().unwrap() += 1;
+                store.insert(i, 0).unwrap();
+            }
+        }
+        // nothing was written while the values fit
+        assert_eq!(store.end, 0);
+        assert_eq!(store.queue.len(), 4);
+
+        store.insert(4, 4).unwrap();
+        store.insert(5, 5).unwrap();
+        assert_eq!(resident(&store), 5);
+        assert!(store.slots[&store.id(&0)].entry.is_none());
+        assert!(store.slots[&store.id(&1)].entry.is_some());
+    }
+
+    #[test]
+    fn compaction_drops_stale_copies() {
+        let mut store = test_store("compaction", 1);
+        for i in 0..4 {
+            store.insert(i, 0).unwrap();
+        }
+        for _ in 0..50 {
+            for i in 0..4 {
+                *store.get_mut(&i).unwrap().unwrap() += 1;
+            }
+        }
+        assert!(store.garbage <= store.end / 2);
+
+        store.compact().unwrap();
+        assert_eq!(store.garbage, 0);
+        for i in 0..4 {
+            assert_eq!(*store.get(&i).unwrap().unwrap(), 50);
+        }
+    }
+
+    #[test]
+    fn file_removed_on_drop() {
+        let store: FileValueStore<u64, u64> = test_store("drop", 1);
+        let path = store.path.clone();
+        assert!(path.exists());
+        drop(store);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn map_syncs_with_in_memory_map() {
+        let mut m1: FileAWORMap<u64, MaxRegister<u64>> =
+            FileAWORMap::with_store(REPLICA_1, test_store("map", 8));
+        let mut m2: AWORMap<u64, MaxRegister<u64>> = AWORMap::new(REPLICA_2);
+
+        for i in 0..100 {
+            m1.try_update(i, |v| v.set(i)).unwrap();
+        }
+        m2.merge_delta(m1.try_take_delta().unwrap().unwrap());
+        assert_eq!(m2.len(), 100);
+
+        m2.update(7, |v| v.set(1000));
+        m2.remove(&8);
+        m1.try_merge_delta(m2.take_delta().unwrap()).unwrap();
+
+        let v = m1.try_get(&7).unwrap().unwrap();
+        assert_eq!(v.value(), Some(&1000));
+        assert!(!m1.contains_key(&8));
+        assert_eq!(m1.len(), 99);
+
+        m1.try_get_mut(&9).unwrap().unwrap().set(2000);
+        let delta = m1.try_take_delta().unwrap().unwrap();
+        m2.merge_delta(delta);
+        assert_eq!(m2.get(&9).unwrap().value(), Some(&2000));
+    }
+
+    #[test]
+    fn takes_deltas_of_changed_values_only() {
+        let mut m1: FileAWORMap<u64, MaxRegister<u64>> =
+            FileAWORMap::with_store(REPLICA_1, test_store("dirty", 2));
+        for i in 0..10 {
+            m1.try_update(i, |v| v.set(i)).unwrap();
+        }
+        m1.try_take_delta().unwrap().unwrap();
+
+        m1.try_get_mut(&3).unwrap().unwrap().set(30);
+        let delta = m1.try_take_delta().unwrap().unwrap();
+        assert_eq!(delta.values().map(|(k, _)| *k).collect::<Vec<_>>(), [3]);
+        assert!(m1.try_take_delta().unwrap().is_none());
+    }
+
+    #[test]
+    fn counters_in_paged_map() {
+        let mut m1: FileAWORMap<u64, GCounter> =
+            FileAWORMap::with_store(REPLICA_1, test_store("counters", 2));
+        for i in 0..20 {
+            m1.try_update(i % 5, |c| c.inc(REPLICA_1).unwrap()).unwrap();
+        }
+        for i in 0..5 {
+            assert_eq!(m1.try_get(&i).unwrap().unwrap().value(), 4);
+        }
+    }
+
+    #[test]
+    fn file_errors_are_returned() {
+        let mut store = test_store("errors", 1);
+        for i in 0..4 {
+            store.insert(i, i).unwrap();
+        }
+        fs::write(&store.path, b"").unwrap();
+
+        let err = store.get(&0).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -1,9 +1,17 @@
-use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use crate::collections::{BTreeMap, BTreeSet};
+use core::cmp::Ordering;
+use core::fmt;
+
+#[cfg(feature = "hlc")]
+mod hlc;
+
+#[cfg(feature = "hlc")]
+pub use hlc::{ClockOverflow, HybridLogicalClock, TimeSource, Timestamp, WallClock};
 
 pub type ReplicaId = u64;
 
 #[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VClock(BTreeMap<ReplicaId, usize>);
 
 impl VClock {
@@ -11,7 +19,6 @@ impl VClock {
         Default::default()
     }
 
-    #[allow(dead_code)]
     pub fn get(&self, replica: &ReplicaId) -> usize {
         self.0.get(replica).map_or(0, |v| *v)
     }
@@ -37,6 +44,39 @@ impl VClock {
         self.partial_cmp(other)
             .is_none_or(|x| x == Ordering::Greater)
     }
+
+    /// Checks if vector clock has seen every event of the other vector clock
+    pub fn descends(&self, other: &Self) -> bool {
+        matches!(
+            self.partial_cmp(other),
+            Some(Ordering::Greater | Ordering::Equal)
+        )
+    }
+
+    /// Checks if neither vector clock has seen all events of the other one
+    pub fn concurrent(&self, other: &Self) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+}
+
+impl FromIterator<(ReplicaId, usize)> for VClock {
+    fn from_iter<I: IntoIterator<Item = (ReplicaId, usize)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// Formats the clock as `{replica: counter, ...}`
+impl fmt::Display for VClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (replica, n)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", replica, n)?;
+        }
+        write!(f, "}}")
+    }
 }
 
 impl PartialOrd for VClock {
@@ -58,6 +98,136 @@ impl PartialOrd for VClock {
     }
 }
 
+/// Scalar logical clock, totally ordered by counter and then by replica id
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LamportClock {
+    counter: u64,
+    replica: ReplicaId,
+}
+
+impl LamportClock {
+    pub fn new(replica: ReplicaId) -> Self {
+        Self {
+            counter: 0,
+            replica,
+        }
+    }
+
+    pub fn get(&self) -> u64 {
+        self.counter
+    }
+
+    pub fn inc(&mut self) -> u64 {
+        self.counter += 1;
+        self.counter
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.counter = self.counter.max(other.counter);
+    }
+}
+
+/// Version vector with extra dots which are not necessarily contiguous
+/// with the vector itself. Used to tag sibling values in a key-value
+/// store, the dot identifies the write and the vector describes its
+/// causal past. Merged versions may hold several such dots.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DottedVersionVector {
+    clock: VClock,
+    dots: BTreeSet<(ReplicaId, usize)>,
+}
+
+impl DottedVersionVector {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Creates a version for a new write made by the replica
+    /// on top of the causal context provided by the client
+    pub fn event(context: VClock, replica: ReplicaId, counter: usize) -> Self {
+        Self {
+            clock: context,
+            dots: BTreeSet::from([(replica, counter)]),
+        }
+    }
+
+    pub fn clock(&self) -> &VClock {
+        &self.clock
+    }
+
+    /// Events beyond the vector, the write's dot for a new version
+    pub fn dots(&self) -> impl Iterator<Item = (ReplicaId, usize)> + '_ {
+        self.dots.iter().copied()
+    }
+
+    /// Checks if the event is covered either by the vector or by a dot
+    pub fn contains(&self, replica: ReplicaId, counter: usize) -> bool {
+        self.clock.get(&replica) >= counter || self.dots.contains(&(replica, counter))
+    }
+
+    /// Joins both versions into a single vector. Dots contiguous with the
+    /// joined vector are folded into it, dots leaving a gap are kept.
+    pub fn merge(&mut self, other: &Self) {
+        self.clock.merge(&other.clock);
+        self.dots.extend(other.dots.iter().copied());
+
+        // dots are ordered by counter within a replica, so folding one
+        // dot may close the gap before the next one
+        let dots = core::mem::take(&mut self.dots);
+        for (replica, counter) in dots {
+            let v = self.clock.0.entry(replica).or_default();
+            if counter <= *v + 1 {
+                *v = (*v).max(counter);
+            } else {
+                self.dots.insert((replica, counter));
+            }
+        }
+        self.clock.0.retain(|_, v| *v > 0);
+    }
+
+    /// Checks if this version has seen every event of the other version
+    pub fn descends(&self, other: &Self) -> bool {
+        let covers_clock = other.clock.0.iter().all(|(replica, counter)| {
+            let n = self.clock.get(replica);
+            n >= *counter
+                || self
+                    .dots
+                    .range((*replica, n + 1)..=(*replica, *counter))
+                    .count()
+                    == counter - n
+        });
+
+        covers_clock && other.dots.iter().all(|(r, n)| self.contains(*r, *n))
+    }
+
+    /// Checks if versions are siblings, i.e. neither descends the other
+    pub fn concurrent(&self, other: &Self) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+}
+
+/// Versions are equal when they cover the same events
+impl PartialEq for DottedVersionVector {
+    fn eq(&self, other: &Self) -> bool {
+        self.descends(other) && other.descends(self)
+    }
+}
+
+impl Eq for DottedVersionVector {}
+
+impl PartialOrd for DottedVersionVector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self.descends(other), other.descends(self)) {
+            (true, true) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Greater),
+            (false, true) => Some(Ordering::Less),
+            (false, false) => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -160,4 +330,142 @@ mod tests {
         assert_eq!(clock1.partial_cmp(&clock2), None);
         assert_ne!(clock1, clock2);
     }
+
+    #[test]
+    fn descends_and_concurrent() {
+        let mut clock1 = VClock::new();
+        let mut clock2 = VClock::new();
+
+        clock1.inc(REPLICA_1);
+        assert!(clock1.descends(&clock2));
+        assert!(!clock2.descends(&clock1));
+        assert!(clock1.descends(&clock1));
+
+        clock2.inc(REPLICA_2);
+        assert!(clock1.concurrent(&clock2));
+        assert!(!clock1.descends(&clock2));
+    }
+
+    #[test]
+    fn lamport_clock() {
+        let mut clock1 = LamportClock::new(REPLICA_1);
+        let mut clock2 = LamportClock::new(REPLICA_2);
+
+        assert_eq!(clock1.inc(), 1);
+        assert_eq!(clock1.inc(), 2);
+        assert_eq!(clock2.inc(), 1);
+        assert!(clock2 < clock1);
+
+        clock2.merge(&clock1);
+        assert_eq!(clock2.get(), 2);
+        assert_eq!(clock2.inc(), 3);
+        assert!(clock1 < clock2);
+
+        clock1.merge(&clock2);
+        // same counter, replica id breaks the tie
+        assert_eq!(clock1.get(), clock2.get());
+        assert!(clock1 < clock2);
+    }
+
+    #[test]
+    fn dvv_compare() {
+        let mut context = VClock::new();
+        context.inc(REPLICA_1);
+
+        let v1 = DottedVersionVector::event(context.clone(), REPLICA_1, 2);
+        let v2 = DottedVersionVector::event(context.clone(), REPLICA_2, 1);
+
+        // both writes were made on top of the same context
+        assert!(v1.concurrent(&v2));
+        assert_eq!(v1.partial_cmp(&v2), None);
+
+        // write made after observing both siblings
+        let mut context = v1.clone();
+        context.merge(&v2);
+        let v3 = DottedVersionVector::event(context.clock().clone(), REPLICA_1, 3);
+
+        assert_eq!(v3.partial_cmp(&v1), Some(Ordering::Greater));
+        assert_eq!(v2.partial_cmp(&v3), Some(Ordering::Less));
+        assert!(v3.descends(&v1) && v3.descends(&v2));
+        assert_eq!(v3.partial_cmp(&v3), Some(Ordering::Equal));
+    }
+
+    #[test]
+    fn dvv_dot_gap() {
+        let mut context = VClock::new();
+        context.inc(REPLICA_1);
+
+        // dot (1, 3) is not contiguous with the vector
+        let v1 = DottedVersionVector::event(context.clone(), REPLICA_1, 3);
+        assert!(v1.contains(REPLICA_1, 1));
+        assert!(!v1.contains(REPLICA_1, 2));
+        assert!(v1.contains(REPLICA_1, 3));
+
+        context.inc(REPLICA_1);
+        let v2 = DottedVersionVector::event(VClock::new(), REPLICA_1, 2);
+        let v3 = DottedVersionVector::event(context, REPLICA_2, 1);
+
+        assert!(v1.concurrent(&v2));
+        assert!(v3.descends(&v2));
+        assert!(v1.concurrent(&v3));
+    }
+
+    #[test]
+    fn dvv_merge() {
+        let v1 = DottedVersionVector::event(VClock::new(), REPLICA_1, 1);
+        let v2 = DottedVersionVector::event(VClock::new(), REPLICA_2, 1);
+
+        let mut v = v1.clone();
+        v.merge(&v2);
+
+        assert_eq!(v.dots().next(), None);
+        assert_eq!(v.clock().get(&REPLICA_1), 1);
+        assert_eq!(v.clock().get(&REPLICA_2), 1);
+        assert!(v.descends(&v1) && v.descends(&v2));
+    }
+
+    #[test]
+    fn dvv_merge_keeps_gap() {
+        let context: VClock = [(REPLICA_1, 1)].into_iter().collect();
+        let v1 = DottedVersionVector::event(context.clone(), REPLICA_1, 3);
+
+        let mut v = DottedVersionVector::new();
+        v.merge(&v1);
+        assert_eq!(v.clock().get(&REPLICA_1), 1);
+        assert_eq!(v.dots().collect::<Vec<_>>(), [(REPLICA_1, 3)]);
+        assert!(!v.contains(REPLICA_1, 2));
+        assert!(v.descends(&v1));
+
+        // the missing event arrives and closes the gap
+        let v2 = DottedVersionVector::event(context, REPLICA_1, 2);
+        v.merge(&v2);
+        assert_eq!(v.clock().get(&REPLICA_1), 3);
+        assert_eq!(v.dots().next(), None);
+        assert!(v.descends(&v1) && v.descends(&v2));
+    }
+
+    #[test]
+    fn dvv_merge_keeps_both_gaps() {
+        let v1 = DottedVersionVector::event([(REPLICA_1, 1)].into_iter().collect(), REPLICA_1, 3);
+        let v2 = DottedVersionVector::event([(REPLICA_2, 1)].into_iter().collect(), REPLICA_2, 3);
+
+        let mut v = v1.clone();
+        v.merge(&v2);
+        assert!(v.descends(&v1) && v.descends(&v2));
+        assert!(!v.contains(REPLICA_1, 2) && !v.contains(REPLICA_2, 2));
+        assert_eq!(v.dots().count(), 2);
+    }
+
+    #[test]
+    fn dvv_eq_matches_order() {
+        // same events, folded into the vector or kept as a dot
+        let v1 = DottedVersionVector::event(VClock::new(), REPLICA_1, 1);
+        let mut v2 = DottedVersionVector::new();
+        v2.merge(&v1);
+
+        assert_eq!(v2.dots().next(), None);
+        assert_eq!(v1.partial_cmp(&v2), Some(Ordering::Equal));
+        assert_eq!(v1, v2);
+        assert_ne!(v1, DottedVersionVector::new());
+    }
 }
@@ -0,0 +1,247 @@
+use super::ReplicaId;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of physical time for a [`HybridLogicalClock`], in milliseconds
+pub trait TimeSource {
+    fn now(&self) -> u64;
+}
+
+/// Wall-clock time since the Unix epoch, only a time source with `std`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WallClock;
+
+#[cfg(feature = "std")]
+impl TimeSource for WallClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64)
+    }
+}
+
+impl<F: Fn() -> u64> TimeSource for F {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// Timestamp issued by a [`HybridLogicalClock`], totally ordered
+/// by physical time, then logical counter, then replica id
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timestamp {
+    pub time: u64,
+    pub counter: u32,
+    pub replica: ReplicaId,
+}
+
+/// Error returned when the logical counter of a [`HybridLogicalClock`]
+/// would overflow, i.e. too many events happen within a single millisecond
+/// or a remote timestamp already carries the maximum counter
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ClockOverflow;
+
+impl fmt::Display for ClockOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "logical clock overflow")
+    }
+}
+
+impl core::error::Error for ClockOverflow {}
+
+#[derive(Debug, Clone)]
+pub struct HybridLogicalClock<T = WallClock> {
+    replica: ReplicaId,
+    time: u64,
+    counter: u32,
+    source: T,
+}
+
+#[cfg(feature = "std")]
+impl HybridLogicalClock {
+    pub fn new(replica: ReplicaId) -> Self {
+        Self::with_time_source(replica, WallClock)
+    }
+}
+
+impl<T: TimeSource> HybridLogicalClock<T> {
+    pub fn with_time_source(replica: ReplicaId, source: T) -> Self {
+        Self {
+            replica,
+            time: 0,
+            counter: 0,
+            source,
+        }
+    }
+
+    /// Returns the latest issued timestamp without advancing the clock
+    pub fn current(&self) -> Timestamp {
+        Timestamp {
+            time: self.time,
+            counter: self.counter,
+            replica: self.replica,
+        }
+    }
+
+    /// Advances the clock for a local or send event, leaving
+    /// it unchanged if the logical counter would overflow
+    pub fn send(&mut self) -> Result<Timestamp, ClockOverflow> {
+        let now = self.source.now();
+        if now > self.time {
+            self.time = now;
+            self.counter = 0;
+        } else {
+            self.counter = self.counter.checked_add(1).ok_or(ClockOverflow)?;
+        }
+        Ok(self.current())
+    }
+
+    /// Advances the clock past a timestamp received from another replica,
+    /// leaving it unchanged if the logical counter would overflow
+    pub fn receive(&mut self, remote: &Timestamp) -> Result<Timestamp, ClockOverflow> {
+        let now = self.source.now();
+        let time = self.time.max(remote.time).max(now);
+
+        let counter = if time == self.time && time == remote.time {
+            self.counter.max(remote.counter).checked_add(1)
+        } else if time == self.time {
+            self.counter.checked_add(1)
+        } else if time == remote.time {
+            remote.counter.checked_add(1)
+        } else {
+            Some(0)
+        };
+        self.counter = counter.ok_or(ClockOverflow)?;
+        self.time = time;
+
+        Ok(self.current())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+    use std::rc::Rc;
+
+    const REPLICA_1: ReplicaId = 123;
+    const REPLICA_2: ReplicaId = 456;
+
+    fn manual_clock(replica: ReplicaId) -> (Rc<Cell<u64>>, HybridLogicalClock<impl TimeSource>) {
+        let now = Rc::new(Cell::new(0));
+        let source = {
+            let now = now.clone();
+            move || now.get()
+        };
+        (now, HybridLogicalClock::with_time_source(replica, source))
+    }
+
+    #[test]
+    fn hlc_send_follows_physical_time() {
+        let (now, mut clock) = manual_clock(REPLICA_1);
+
+        now.set(10);
+        let t1 = clock.send().unwrap();
+        assert_eq!((t1.time, t1.counter), (10, 0));
+
+        now.set(20);
+        let t2 = clock.send().unwrap();
+        assert_eq!((t2.time, t2.counter), (20, 0));
+        assert!(t1 < t2);
+    }
+
+    #[test]
+    fn hlc_send_without_physical_progress() {
+        let (now, mut clock) = manual_clock(REPLICA_1);
+
+        now.set(10);
+        let t1 = clock.send().unwrap();
+        let t2 = clock.send().unwrap();
+
+        // physical clock goes backwards
+        now.set(5);
+        let t3 = clock.send().unwrap();
+
+        assert_eq!((t2.time, t2.counter), (10, 1));
+        assert_eq!((t3.time, t3.counter), (10, 2));
+        assert!(t1 < t2 && t2 < t3);
+    }
+
+    #[test]
+    fn hlc_receive_from_future() {
+        let (now1, mut clock1) = manual_clock(REPLICA_1);
+        let (now2, mut clock2) = manual_clock(REPLICA_2);
+
+        now1.set(100);
+        let t1 = clock1.send().unwrap();
+        let t1 = clock1.receive(&t1).unwrap();
+
+        // clock2 lags behind clock1
+        now2.set(50);
+        let t2 = clock2.receive(&t1).unwrap();
+
+        assert_eq!((t2.time, t2.counter), (100, 2));
+        assert!(t1 < t2);
+
+        let t3 = clock2.send().unwrap();
+        assert_eq!((t3.time, t3.counter), (100, 3));
+    }
+
+    #[test]
+    fn hlc_receive_from_past() {
+        let (now1, mut clock1) = manual_clock(REPLICA_1);
+        let (now2, mut clock2) = manual_clock(REPLICA_2);
+
+        now1.set(10);
+        let t1 = clock1.send().unwrap();
+
+        now2.set(20);
+        let t2 = clock2.receive(&t1).unwrap();
+
+        assert_eq!((t2.time, t2.counter), (20, 0));
+        assert!(t1 < t2);
+    }
+
+    #[test]
+    fn hlc_total_order() {
+        let (now1, mut clock1) = manual_clock(REPLICA_1);
+        let (now2, mut clock2) = manual_clock(REPLICA_2);
+
+        now1.set(10);
+        now2.set(10);
+
+        let t1 = clock1.send().unwrap();
+        let t2 = clock2.send().unwrap();
+
+        // same physical time and counter, replica id breaks the tie
+        assert_eq!(t1.cmp(&t2), Ordering::Less);
+        assert_ne!(t1, t2);
+    }
+
+    #[test]
+    fn hlc_counter_overflow() {
+        let (now, mut clock) = manual_clock(REPLICA_1);
+
+        now.set(10);
+        let remote = Timestamp {
+            time: 10,
+            counter: u32::MAX,
+            replica: REPLICA_2,
+        };
+        assert_eq!(clock.receive(&remote), Err(ClockOverflow));
+        assert_eq!((clock.current().time, clock.current().counter), (0, 0));
+
+        clock.counter = u32::MAX - 1;
+        clock.time = 10;
+        assert_eq!(clock.send().unwrap().counter, u32::MAX);
+        assert_eq!(clock.send(), Err(ClockOverflow));
+        assert_eq!(clock.current().counter, u32::MAX);
+
+        // physical time moving on resets the counter
+        now.set(11);
+        assert_eq!(clock.send().unwrap().counter, 0);
+    }
+}